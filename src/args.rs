@@ -1,5 +1,7 @@
-use crate::protocol::device_info::FlashlightMode;
+use crate::output::OutputFormat;
+use crate::protocol::device_info::{FlashlightMode, OutputSwitch, Setting};
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 pub struct Args {
@@ -9,18 +11,125 @@ pub struct Args {
     /// Filter for the name of the bluetooth device
     #[arg(short, long, default_value = "ugreen gs")]
     pub device_name: String,
+
+    /// Use a simulated device instead of connecting over bluetooth
+    #[arg(long, conflicts_with = "replay")]
+    pub simulate: bool,
+
+    /// Read notifications from a capture made with `record` instead of connecting over bluetooth
+    #[arg(long)]
+    pub replay: Option<PathBuf>,
+
+    /// Replay the capture with the original delays between notifications
+    #[arg(long, requires = "replay")]
+    pub original_speed: bool,
+
+    /// Output format of commands that show the device state
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    pub format: OutputFormat,
+
+    /// How many times to repeat a command that the device did not confirm
+    #[arg(long, default_value_t = 2)]
+    pub retries: u32,
 }
 
 #[derive(Subcommand)]
 pub enum Command {
     Status,
     Connect,
+    /// Keep showing the current status as it changes
+    Watch,
+    /// Full-screen dashboard with history graphs and flashlight control
+    Dashboard,
+    /// Show the Bluetooth name and firmware version
+    Info,
     Flashlight {
         #[command(subcommand)]
         mode: Option<FlashlightMode>,
     },
-    Exporter {
-        #[arg(short, long, default_value_t = 9091)]
-        port: u16,
+    /// Show whether an output bank is on
+    Output {
+        #[arg(value_enum)]
+        output: OutputSwitch,
+    },
+    /// Show device settings
+    Settings {
+        #[command(subcommand)]
+        setting: SettingsCommand,
+    },
+    Exporter(ExporterArgs),
+    /// Send an arbitrary request and print the notifications that follow it
+    Raw {
+        /// Request key, e.g. 0x20
+        #[arg(value_parser = parse_key)]
+        key: u8,
+        /// Payload as hex, can be split into multiple arguments
+        payload: Vec<String>,
+        /// How many seconds to listen for notifications after sending the request
+        #[arg(short, long, default_value_t = 5)]
+        wait: u64,
+    },
+    /// Print every received frame in detail, for protocol debugging
+    Sniff,
+    /// Decode the requests and notifications in an Android btsnoop HCI log
+    DecodeBtsnoop {
+        file: PathBuf,
+    },
+    /// Save all raw notifications to a file, to be used with `--replay`
+    Record {
+        #[arg(short, long)]
+        out: PathBuf,
     },
 }
+
+#[derive(clap::Args)]
+pub struct ExporterArgs {
+    #[arg(short, long, default_value_t = 9091)]
+    pub port: u16,
+    /// Seconds without updates after which `powerroam_up` is set to 0
+    #[arg(long, default_value_t = 60)]
+    pub stale_after: u64,
+    /// Keep the energy counters in this file so they survive restarts
+    #[arg(long)]
+    pub energy_file: Option<PathBuf>,
+    /// Name or address of a connected device to export, can be repeated. Defaults to `--device-name`
+    #[arg(long = "device")]
+    pub devices: Vec<String>,
+}
+
+#[derive(Subcommand)]
+pub enum SettingsCommand {
+    /// AC output frequency
+    AcFrequency,
+    /// AC turbo mode
+    Turbo,
+    /// Low-noise charging
+    LowNoise,
+    /// Beep on key presses
+    KeyBeep,
+    /// Voice warnings
+    WarningVoice,
+    /// Front panel lock
+    ChildLock,
+    /// Automatic standby
+    Standby,
+}
+
+impl From<SettingsCommand> for Setting {
+    fn from(command: SettingsCommand) -> Self {
+        match command {
+            SettingsCommand::AcFrequency => Setting::AcFrequency,
+            SettingsCommand::Turbo => Setting::AcTurbo,
+            SettingsCommand::LowNoise => Setting::LowNoise,
+            SettingsCommand::KeyBeep => Setting::KeyVoice,
+            SettingsCommand::WarningVoice => Setting::WarningVoice,
+            SettingsCommand::ChildLock => Setting::Locking,
+            SettingsCommand::Standby => Setting::Standby,
+        }
+    }
+}
+
+fn parse_key(value: &str) -> Result<u8, String> {
+    let digits = value.strip_prefix("0x").unwrap_or(value);
+    u8::from_str_radix(digits, 16).map_err(|err| err.to_string())
+}
@@ -0,0 +1,229 @@
+//! Parser for btsnoop HCI logs, as written by Android's "Bluetooth HCI snoop log" developer option
+use anyhow::Context;
+use std::collections::HashMap;
+
+const MAGIC: &[u8; 8] = b"btsnoop\0";
+const HEADER_LEN: usize = 16;
+const RECORD_HEADER_LEN: usize = 24;
+/// HCI packets without a packet type indicator
+const DATALINK_HCI: u32 = 1001;
+/// HCI packets prefixed with the UART (H4) packet type
+const DATALINK_H4: u32 = 1002;
+const H4_ACL: u8 = 0x02;
+const FLAG_RECEIVED: u32 = 0x01;
+const FLAG_COMMAND_OR_EVENT: u32 = 0x02;
+/// Packet boundary flag of an ACL fragment continuing the previous one
+const PB_CONTINUATION: u16 = 0b01;
+const ATT_CID: u16 = 0x0004;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AttOperation {
+    WriteRequest,
+    WriteCommand,
+    Notification,
+    Indication,
+}
+
+impl AttOperation {
+    fn from_opcode(opcode: u8) -> Option<Self> {
+        match opcode {
+            0x12 => Some(Self::WriteRequest),
+            0x52 => Some(Self::WriteCommand),
+            0x1b => Some(Self::Notification),
+            0x1d => Some(Self::Indication),
+            _ => None,
+        }
+    }
+
+    pub fn is_write(self) -> bool {
+        matches!(self, Self::WriteRequest | Self::WriteCommand)
+    }
+}
+
+/// An attribute value written to or notified by a device
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AttValue {
+    /// Microseconds since the first record of the log
+    pub time: i64,
+    pub connection: u16,
+    pub operation: AttOperation,
+    pub handle: u16,
+    pub value: Vec<u8>,
+}
+
+/// Extracts all ATT writes, notifications and indications from a btsnoop log
+pub fn parse(data: &[u8]) -> anyhow::Result<Vec<AttValue>> {
+    let header = data.get(..HEADER_LEN).context("File is too short")?;
+    if &header[..8] != MAGIC {
+        anyhow::bail!("Not a btsnoop file");
+    }
+    let datalink = u32::from_be_bytes(header[12..16].try_into().unwrap());
+    if datalink != DATALINK_HCI && datalink != DATALINK_H4 {
+        anyhow::bail!("Unsupported datalink type {datalink}");
+    }
+
+    let mut values = Vec::new();
+    let mut reassembler = Reassembler::default();
+    let mut first_timestamp = None;
+    let mut offset = HEADER_LEN;
+
+    while offset < data.len() {
+        let header = data
+            .get(offset..offset + RECORD_HEADER_LEN)
+            .with_context(|| format!("Truncated record header at offset {offset}"))?;
+        let included_len = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+        let flags = u32::from_be_bytes(header[8..12].try_into().unwrap());
+        let timestamp = i64::from_be_bytes(header[16..24].try_into().unwrap());
+
+        let start = offset + RECORD_HEADER_LEN;
+        let mut packet = data
+            .get(start..start + included_len)
+            .with_context(|| format!("Truncated record at offset {offset}"))?;
+        offset = start + included_len;
+
+        let time = timestamp - *first_timestamp.get_or_insert(timestamp);
+
+        if datalink == DATALINK_H4 {
+            match packet.split_first() {
+                Some((&H4_ACL, rest)) => packet = rest,
+                _ => continue,
+            }
+        } else if flags & FLAG_COMMAND_OR_EVENT != 0 {
+            continue;
+        }
+
+        let received = flags & FLAG_RECEIVED != 0;
+        if let Some((connection, pdu)) = reassembler.push(received, packet) {
+            if let Some((operation, handle, value)) = parse_att(&pdu) {
+                values.push(AttValue {
+                    time,
+                    connection,
+                    operation,
+                    handle,
+                    value: value.to_vec(),
+                });
+            }
+        }
+    }
+
+    Ok(values)
+}
+
+/// Joins ACL fragments into ATT PDUs, separately for every connection and direction
+#[derive(Default)]
+struct Reassembler {
+    pending: HashMap<(u16, bool), Vec<u8>>,
+}
+
+impl Reassembler {
+    fn push(&mut self, received: bool, packet: &[u8]) -> Option<(u16, Vec<u8>)> {
+        if packet.len() < 4 {
+            return None;
+        }
+        let handle_flags = u16::from_le_bytes([packet[0], packet[1]]);
+        let connection = handle_flags & 0x0fff;
+        let boundary = (handle_flags >> 12) & 0b11;
+        let data = &packet[4..];
+
+        let key = (connection, received);
+        let buf = if boundary == PB_CONTINUATION {
+            let buf = self.pending.get_mut(&key)?;
+            buf.extend_from_slice(data);
+            buf
+        } else {
+            self.pending.insert(key, data.to_vec());
+            self.pending.get_mut(&key).unwrap()
+        };
+
+        if buf.len() < 4 {
+            return None;
+        }
+        let len = usize::from(u16::from_le_bytes([buf[0], buf[1]]));
+        if buf.len() < len + 4 {
+            return None;
+        }
+
+        let buf = self.pending.remove(&key).unwrap();
+        let cid = u16::from_le_bytes([buf[2], buf[3]]);
+        (cid == ATT_CID).then(|| (connection, buf[4..len + 4].to_vec()))
+    }
+}
+
+fn parse_att(pdu: &[u8]) -> Option<(AttOperation, u16, &[u8])> {
+    let operation = AttOperation::from_opcode(*pdu.first()?)?;
+    let handle = u16::from_le_bytes([*pdu.get(1)?, *pdu.get(2)?]);
+    Some((operation, handle, &pdu[3..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, AttOperation, AttValue, DATALINK_H4, FLAG_RECEIVED, MAGIC};
+    use pretty_assertions::assert_eq;
+
+    fn log(records: &[(u32, i64, Vec<u8>)]) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(&DATALINK_H4.to_be_bytes());
+        for (flags, timestamp, packet) in records {
+            let len = packet.len() as u32;
+            data.extend_from_slice(&len.to_be_bytes());
+            data.extend_from_slice(&len.to_be_bytes());
+            data.extend_from_slice(&flags.to_be_bytes());
+            data.extend_from_slice(&0u32.to_be_bytes());
+            data.extend_from_slice(&timestamp.to_be_bytes());
+            data.extend_from_slice(packet);
+        }
+        data
+    }
+
+    fn acl(boundary: u16, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x02];
+        packet.extend_from_slice(&(0x0040 | boundary << 12).to_le_bytes());
+        packet.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn l2cap(att: &[u8]) -> Vec<u8> {
+        let mut pdu = (att.len() as u16).to_le_bytes().to_vec();
+        pdu.extend_from_slice(&0x0004u16.to_le_bytes());
+        pdu.extend_from_slice(att);
+        pdu
+    }
+
+    #[test]
+    fn write_and_fragmented_notification() {
+        let write = l2cap(&[0x52, 0x10, 0x00, 0x5a, 0xa5]);
+        let notification = l2cap(&[0x1b, 0x12, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05]);
+        let data = log(&[
+            (0, 1000, acl(0b00, &write)),
+            // HCI event, should be skipped
+            (FLAG_RECEIVED | 0x02, 1500, vec![0x04, 0x13, 0x00]),
+            (FLAG_RECEIVED, 2000, acl(0b10, &notification[..6])),
+            (FLAG_RECEIVED, 2500, acl(0b01, &notification[6..])),
+        ]);
+
+        let expected = vec![
+            AttValue {
+                time: 0,
+                connection: 0x40,
+                operation: AttOperation::WriteCommand,
+                handle: 0x10,
+                value: vec![0x5a, 0xa5],
+            },
+            AttValue {
+                time: 1500,
+                connection: 0x40,
+                operation: AttOperation::Notification,
+                handle: 0x12,
+                value: vec![0x01, 0x02, 0x03, 0x04, 0x05],
+            },
+        ];
+        assert_eq!(expected, parse(&data).unwrap());
+    }
+
+    #[test]
+    fn not_btsnoop() {
+        assert!(parse(b"not a btsnoop file").is_err());
+    }
+}
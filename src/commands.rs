@@ -1,158 +1,644 @@
 use crate::{
-    exporter,
+    btsnoop, display,
+    exporter::{self, energy::EnergyFile, Metrics},
+    hex,
+    output::{self, OutputFormat},
     protocol::{
-        device_info::{build_device_info, FlashlightMode},
-        notification::{process_notification, StatsUpdate},
-        request::request_to_buf,
+        device_info::{
+            build_device_info, DeviceInfo, FlashlightMode, Setting, SettingValue, Status,
+            VersionInfo,
+        },
+        error::ParseError,
+        frame::{Frame, FrameDecoder},
+        notification::{process_frame, process_notification, StatsUpdate},
+        request::Request,
     },
+    transport::{
+        bluetooth::BluetoothTransport,
+        replay::{format_capture_entry, CaptureEntry},
+        Discover, NotReconnectableError, Transport,
+    },
+    tui,
 };
 use anyhow::Context;
-use btleplug::{
-    api::{Central, CentralEvent, CharPropFlags, Peripheral as _, ScanFilter, WriteType},
-    platform::{Adapter, Peripheral},
+use btleplug::platform::Adapter;
+use futures::{future, stream, Stream, StreamExt};
+use prometheus::Registry;
+use serde::Serialize;
+use std::{
+    cell::{OnceCell, RefCell},
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::{self, File},
+    io::{LineWriter, Write},
+    path::Path,
+    time::{Duration, SystemTime},
 };
-use futures::{stream, Stream, StreamExt};
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// The device did not report the requested state after all attempts
+#[derive(Debug)]
+pub struct NotConfirmedError {
+    pub request: Request,
+    pub attempts: u32,
+}
+
+impl fmt::Display for NotConfirmedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Device did not confirm {:?} after {} attempts",
+            self.request, self.attempts
+        )
+    }
+}
+
+impl std::error::Error for NotConfirmedError {}
 
 pub async fn connect(adapter: &Adapter, device_name: &str) -> anyhow::Result<()> {
     println!("Scanning for devices...");
 
-    let mut events = adapter.events().await?;
-    adapter
-        .start_scan(ScanFilter::default())
+    BluetoothTransport::connect(adapter, device_name).await?;
+    println!("Connected");
+
+    Ok(())
+}
+
+pub async fn status(transport: &impl Transport, format: OutputFormat) -> anyhow::Result<()> {
+    output::print(format, &query_status(transport).await?)
+}
+
+async fn query_status(transport: &impl Transport) -> anyhow::Result<DeviceInfo> {
+    let updates_stream = log_parse_errors(setup_stats_stream(transport).await?);
+
+    build_device_info(updates_stream)
         .await
-        .context("Could not start scan")?;
+        .context("Could not collect device info")
+}
+
+pub async fn watch(transport: &impl Transport) -> anyhow::Result<()> {
+    let mut stream = log_parse_errors(setup_stats_stream(transport).await?);
+
+    let mut device_info = build_device_info(&mut stream)
+        .await
+        .context("Could not collect device info")?;
+    let mut shown = String::new();
+
+    loop {
+        let view = display::compact_status(&device_info);
+        if view != shown {
+            // Clear the screen and move the cursor to the top
+            println!("\x1b[2J\x1b[H{view}");
+            shown = view;
+        }
+
+        match stream.next().await {
+            Some(update) => device_info.apply_update(update),
+            None => break,
+        }
+    }
+
+    println!("Notification stream ended");
+    Ok(())
+}
+
+pub async fn dashboard(transport: &impl Transport, retries: u32) -> anyhow::Result<()> {
+    let name = transport.name().await?;
+    let stream = setup_stats_stream(transport).await?;
+    tui::run(transport, &name, stream, retries).await
+}
+
+#[derive(Serialize)]
+struct InfoOutput {
+    /// Bluetooth name of the device
+    name: String,
+    #[serde(flatten)]
+    version: VersionInfo,
+}
+
+impl fmt::Display for InfoOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Bluetooth name: {}", self.name)?;
+        writeln!(f, "Firmware version: {}", self.version.firmware)?;
+        write!(f, "Hardware version: {}", self.version.hardware)?;
+        if let Some(serial_number) = &self.version.serial_number {
+            write!(f, "\nSerial number: {serial_number}")?;
+        }
+        write!(f, "\nRaw response: {}", self.version.raw)
+    }
+}
+
+pub async fn info(
+    transport: &impl Transport,
+    retries: u32,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let name = transport.name().await?;
+    let version = query_version(transport, retries).await?;
+
+    output::print(format, &InfoOutput { name, version })
+}
+
+async fn query_version(transport: &impl Transport, retries: u32) -> anyhow::Result<VersionInfo> {
+    let mut stream = log_parse_errors(setup_stats_stream(transport).await?);
+
+    match write_confirmed(transport, &mut stream, Request::Version, retries).await? {
+        StatsUpdate::Version(version) => Ok(version),
+        update => anyhow::bail!("Unexpected response to the version request: {update:?}"),
+    }
+}
+
+/// Finds the already connected device
+pub async fn find_device(
+    adapter: &Adapter,
+    device_name: &str,
+) -> anyhow::Result<BluetoothTransport> {
+    BluetoothTransport::discover(adapter, device_name)
+        .await?
+        .with_context(|| format!("Not connected to a device matching '{device_name}'"))
+}
+
+async fn setup_stats_stream(
+    transport: &impl Transport,
+) -> anyhow::Result<impl Stream<Item = Result<StatsUpdate, ParseError>> + Unpin> {
+    let notification_stream = transport.subscribe().await?;
+
+    let mut decoder = FrameDecoder::default();
+    let updates_stream = notification_stream
+        .flat_map(move |value| stream::iter(process_notification(&mut decoder, &value)));
+    Ok(updates_stream)
+}
+
+async fn write_request(transport: &impl Transport, request: Request) -> anyhow::Result<()> {
+    transport.write(&request.encode()).await
+}
+
+/// Writes the request and waits for the device to confirm it, repeating the write if there is no confirmation in time
+async fn write_confirmed(
+    transport: &impl Transport,
+    stream: &mut (impl Stream<Item = StatsUpdate> + Unpin),
+    request: Request,
+    retries: u32,
+) -> anyhow::Result<StatsUpdate> {
+    write_confirmed_with(transport, stream, request, retries, |attempt| {
+        eprintln!("Device did not confirm the request, retrying ({attempt}/{retries})")
+    })
+    .await
+}
+
+/// Same as [`write_confirmed`], but reports retries to `on_retry` instead of printing them
+pub async fn write_confirmed_with(
+    transport: &impl Transport,
+    stream: &mut (impl Stream<Item = StatsUpdate> + Unpin),
+    request: Request,
+    retries: u32,
+    mut on_retry: impl FnMut(u32),
+) -> anyhow::Result<StatsUpdate> {
+    let attempts = retries + 1;
 
-    while let Some(event) = events.next().await {
-        if let CentralEvent::DeviceDiscovered(peripheral_id) = event {
-            let peripheral = adapter.peripheral(&peripheral_id).await?;
-            let properties = peripheral.properties().await?;
+    for attempt in 1..=attempts {
+        write_request(transport, request).await?;
 
-            if let Some(name) = properties.and_then(|props| props.local_name) {
-                if name.contains(device_name) {
-                    println!("Found device '{name}', connecting");
-                    return connect_device(adapter, peripheral).await;
+        let confirmation = async {
+            while let Some(update) = stream.next().await {
+                if request.is_confirmed_by(&update) {
+                    return Some(update);
                 }
             }
+            None
+        };
+
+        match tokio::time::timeout(RESPONSE_TIMEOUT, confirmation).await {
+            Ok(Some(update)) => return Ok(update),
+            Ok(None) => anyhow::bail!("Notification stream ended"),
+            Err(_) if attempt < attempts => on_retry(attempt),
+            Err(_) => (),
         }
     }
-    todo!();
+
+    Err(NotConfirmedError { request, attempts }.into())
 }
 
-async fn connect_device(adapter: &Adapter, peripheral: Peripheral) -> anyhow::Result<()> {
-    adapter.stop_scan().await?;
-    peripheral.connect().await.context("Failed to connect")?;
-    println!("Connected");
+/// Prints and skips updates that could not be parsed
+fn log_parse_errors(
+    stream: impl Stream<Item = Result<StatsUpdate, ParseError>> + Unpin,
+) -> impl Stream<Item = StatsUpdate> + Unpin {
+    stream.filter_map(|result| {
+        future::ready(match result {
+            Ok(update) => Some(update),
+            Err(err) => {
+                eprintln!("Could not parse notification: {err}");
+                None
+            }
+        })
+    })
+}
+
+pub async fn exporter(
+    devices: &[impl Discover],
+    port: u16,
+    stale_after: Duration,
+    energy_file: Option<&Path>,
+) -> anyhow::Result<()> {
+    let registry = prometheus::default_registry();
+    // The same device given twice is only exported once
+    let mut filters = HashSet::new();
+    let devices: Vec<_> = devices
+        .iter()
+        .filter(|device| filters.insert(device.filter().to_lowercase()))
+        .collect();
 
+    let saved = RefCell::new(match energy_file {
+        Some(path) => exporter::energy::load(path)?,
+        None => EnergyFile::default(),
+    });
+    let claimed = RefCell::new(HashMap::new());
+    let metrics: Vec<OnceCell<Metrics>> = devices.iter().map(|_| OnceCell::new()).collect();
+    exporter::start(port)?;
+
+    let collectors = devices.iter().zip(&metrics).map(|(device, cell)| {
+        export_device(registry, *device, cell, &saved, &claimed, stale_after)
+    });
+    let persist_energy = async {
+        match energy_file {
+            Some(path) => exporter::persist_energy(&metrics, &saved, path).await,
+            None => future::pending().await,
+        }
+    };
+    let result = tokio::select! {
+        _ = future::join_all(collectors) => Err(anyhow::anyhow!("Stopped collecting from all devices")),
+        never = persist_energy => never,
+        result = exporter::shutdown_signal() => result.context("Could not wait for a shutdown signal"),
+    };
+
+    let saved = match energy_file {
+        Some(path) => exporter::save_energy(&metrics, &saved, path),
+        None => Ok(()),
+    };
+    result?;
+    saved
+}
+
+/// Exports the device until collecting from it fails for good, then marks it as down.
+///
+/// The failure is only logged, so that the other devices keep being exported.
+async fn export_device(
+    registry: &Registry,
+    device: &impl Discover,
+    cell: &OnceCell<Metrics>,
+    saved: &RefCell<EnergyFile>,
+    claimed: &RefCell<HashMap<String, String>>,
+    stale_after: Duration,
+) {
+    let filter = device.filter();
+    let result = async {
+        let transport = discover_device(registry, device).await?;
+        let metrics = device_metrics(registry, transport, &filter, claimed).await?;
+        if let Some(totals) = saved.borrow().get(metrics.address()) {
+            metrics.restore_energy(totals);
+        }
+        let metrics = cell.get_or_init(|| metrics);
+
+        tokio::select! {
+            result = export_metrics(transport, metrics) => result,
+            never = exporter::watch_staleness(metrics, stale_after) => never,
+        }
+    };
+
+    if let Err(err) = result.await {
+        eprintln!("{filter}: {err:#}");
+        if let Some(metrics) = cell.get() {
+            metrics.stop();
+        }
+    }
+}
+
+/// Looks for the device with an increasing delay until it is found,
+/// exporting it as not connected in the meantime
+async fn discover_device<'a, D: Discover>(
+    registry: &Registry,
+    device: &'a D,
+) -> anyhow::Result<&'a D::Transport> {
+    let filter = device.filter();
+    let mut delay = INITIAL_RECONNECT_DELAY;
+    let mut missing = None;
+
+    loop {
+        match device.discover().await {
+            Ok(Some(transport)) => {
+                if let Some(placeholder) = missing {
+                    registry.unregister(Box::new(placeholder))?;
+                }
+                return Ok(transport);
+            }
+            Ok(None) => eprintln!("{filter}: Not connected to a matching device"),
+            Err(err) => eprintln!("{filter}: Could not look for the device: {err:#}"),
+        }
+        if missing.is_none() {
+            missing = Some(exporter::register_missing(registry, &filter)?);
+        }
+
+        eprintln!(
+            "{filter}: Looking for the device again in {}s",
+            delay.as_secs()
+        );
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Creates the metrics of a discovered device, fails if it was already found with another filter
+async fn device_metrics(
+    registry: &Registry,
+    transport: &impl Transport,
+    filter: &str,
+    claimed: &RefCell<HashMap<String, String>>,
+) -> anyhow::Result<Metrics> {
+    let address = transport.address();
+    if let Some(other) = claimed
+        .borrow_mut()
+        .insert(address.clone(), filter.to_owned())
+    {
+        anyhow::bail!(
+            "'{other}' and '{filter}' both match the device {address}, pass each device only once"
+        );
+    }
+
+    let name = transport.name().await?;
+    Ok(Metrics::new(registry, &name, &address)?)
+}
+
+/// Collects metrics from the device, reconnecting with an increasing delay whenever the connection is lost.
+///
+/// Only returns if the transport can never be reconnected.
+async fn export_metrics(transport: &impl Transport, metrics: &Metrics) -> anyhow::Result<()> {
+    let address = metrics.address();
+    let mut delay = INITIAL_RECONNECT_DELAY;
+
+    loop {
+        match setup_stats_stream(transport).await {
+            Ok(stream) => {
+                if let Err(err) = write_request(transport, Request::Version).await {
+                    eprintln!("{address}: Could not request the firmware version: {err:#}");
+                }
+
+                if exporter::collect(metrics, stream).await {
+                    delay = INITIAL_RECONNECT_DELAY;
+                }
+                metrics.set_connected(false);
+                eprintln!("{address}: Notification stream ended");
+            }
+            Err(err) => eprintln!("{address}: Could not subscribe to notifications: {err:#}"),
+        }
+
+        loop {
+            eprintln!("{address}: Reconnecting in {}s", delay.as_secs());
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+
+            match transport.reconnect().await {
+                Ok(()) => {
+                    metrics.reconnected();
+                    break;
+                }
+                Err(err) if err.is::<NotReconnectableError>() => {
+                    return Err(err.context(format!("Stopped collecting from {address}")))
+                }
+                Err(err) => eprintln!("{address}: Could not reconnect: {err:#}"),
+            }
+        }
+    }
+}
+
+// `is_multiple_of` would require Rust 1.87
+#[allow(clippy::manual_is_multiple_of)]
+pub async fn record(transport: &impl Transport, path: &Path) -> anyhow::Result<()> {
+    let file = File::create(path)
+        .with_context(|| format!("Could not create capture file '{}'", path.display()))?;
+    let mut writer = LineWriter::new(file);
+    writeln!(writer, "# {}", transport.name().await?)?;
+
+    let mut notifications = transport.subscribe().await?;
+    println!("Recording notifications to '{}'", path.display());
+
+    let mut count = 0u32;
+    while let Some(data) = notifications.next().await {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs_f64();
+        writeln!(
+            writer,
+            "{}",
+            format_capture_entry(&CaptureEntry { timestamp, data })
+        )?;
+
+        count += 1;
+        if count % 100 == 0 {
+            println!("Recorded {count} notifications");
+        }
+    }
+
+    println!("Notification stream ended after {count} notifications");
     Ok(())
 }
 
-pub async fn status(adapter: &Adapter, device_name: &str) -> anyhow::Result<()> {
-    let peripheral = get_connected_device(adapter, device_name)
-        .await?
-        .context("Not connected to a device")?;
+/// Prints every received frame along with its checksum status and decoded contents
+pub async fn sniff(transport: &impl Transport) -> anyhow::Result<()> {
+    let mut notifications = transport.subscribe().await?;
+    let mut decoder = FrameDecoder::default();
 
-    let updates_stream = setup_stats_stream(peripheral).await?;
+    while let Some(notification) = notifications.next().await {
+        for result in decoder.decode_raw(&notification) {
+            let raw = match result {
+                Ok(raw) => raw,
+                Err(err) => {
+                    println!("error: {err}");
+                    continue;
+                }
+            };
 
-    let device_info = build_device_info(updates_stream)
-        .await
-        .context("Could not collect device info")?;
+            let frame = &raw.frame;
+            let (checksum, decoded) = if raw.checksum_valid() {
+                let decoded = match process_frame(frame) {
+                    Ok(StatsUpdate::Unknown { .. }) => "unknown".to_owned(),
+                    Ok(update) => format!("{update:?}"),
+                    Err(err) => format!("error: {err}"),
+                };
+                ("ok".to_owned(), decoded)
+            } else {
+                (
+                    format!(
+                        "BAD (expected {:#06x}, calculated {:#06x})",
+                        raw.checksum, raw.calculated_checksum
+                    ),
+                    "-".to_owned(),
+                )
+            };
 
-    println!("{device_info:#?}");
+            println!(
+                "key {:#04x} len {:>3} crc {checksum} | {} | {decoded}",
+                frame.key,
+                frame.payload.len(),
+                hex::encode_spaced(&frame.payload),
+            );
+        }
+    }
+
+    println!("Notification stream ended");
     Ok(())
 }
 
-async fn get_connected_device(
-    adapter: &Adapter,
-    device_name: &str,
-) -> anyhow::Result<Option<Peripheral>> {
-    let peripherals = adapter.peripherals().await?;
-
-    for peripheral in peripherals {
-        if let Some(properties) = peripheral.properties().await? {
-            if let Some(name) = properties.local_name {
-                if name.contains(device_name) {
-                    return Ok(Some(peripheral));
+/// Writes an arbitrary request frame and prints all updates received within `duration`
+pub async fn raw(
+    transport: &impl Transport,
+    key: u8,
+    payload: &[String],
+    duration: Duration,
+) -> anyhow::Result<()> {
+    let payload = hex::decode(&payload.concat()).context("Invalid payload")?;
+    let data = Frame { key, payload }.encode_request();
+
+    let mut stream = setup_stats_stream(transport).await?;
+    transport.write(&data).await?;
+    println!("Sent {}", hex::encode_spaced(&data));
+
+    let print_updates = async {
+        while let Some(result) = stream.next().await {
+            match result {
+                Ok(StatsUpdate::Unknown { key, payload }) => {
+                    println!("UNKNOWN key {key:#04x}: {}", hex::encode_spaced(&payload))
                 }
+                Ok(update) => println!("{update:?}"),
+                Err(err) => println!("error: {err}"),
             }
         }
-    }
+    };
 
-    Ok(None)
+    if tokio::time::timeout(duration, print_updates).await.is_ok() {
+        println!("Notification stream ended");
+    }
+    Ok(())
 }
 
-async fn setup_stats_stream(
-    peripheral: Peripheral,
-) -> anyhow::Result<impl Stream<Item = StatsUpdate> + Unpin> {
-    peripheral
-        .discover_services()
-        .await
-        .context("Could not discover services")?;
+/// Prints the PowerRoam requests and notifications found in an Android btsnoop HCI log
+pub fn decode_btsnoop(path: &Path) -> anyhow::Result<()> {
+    let data = fs::read(path).with_context(|| format!("Could not read '{}'", path.display()))?;
+    let values = btsnoop::parse(&data).context("Could not parse btsnoop log")?;
 
-    let notify_characteristic = peripheral
-        .characteristics()
-        .into_iter()
-        .find(|characteristic| characteristic.properties.contains(CharPropFlags::NOTIFY))
-        .context("Could not find a notify characteristic")?;
+    let mut decoders: HashMap<(u16, u16, bool), FrameDecoder> = HashMap::new();
+    for value in values {
+        let is_write = value.operation.is_write();
+        let decoder = decoders
+            .entry((value.connection, value.handle, is_write))
+            .or_insert_with(|| {
+                if is_write {
+                    FrameDecoder::for_requests()
+                } else {
+                    FrameDecoder::default()
+                }
+            });
 
-    peripheral
-        .subscribe(&notify_characteristic)
-        .await
-        .context("Could not subscribe to characteristic")?;
+        let prefix = format!(
+            "{:>10.3} {} {:#06x}",
+            value.time as f64 / 1_000_000.0,
+            if is_write { "write " } else { "notify" },
+            value.handle
+        );
 
-    let notification_stream = peripheral.notifications().await?;
+        for result in decoder.decode(&value.value) {
+            let frame = match result {
+                Ok(frame) => frame,
+                Err(err) => {
+                    println!("{prefix} error: {err}");
+                    continue;
+                }
+            };
 
-    let updates_stream = notification_stream
-        .skip(1)
-        .flat_map(|notification| stream::iter(process_notification(&notification.value)));
-    Ok(updates_stream)
+            if is_write {
+                match Request::from_frame(&frame) {
+                    Some(request) => println!("{prefix} {request:?}"),
+                    None => println!(
+                        "{prefix} UNKNOWN request key {:#04x}: {}",
+                        frame.key,
+                        hex::encode_spaced(&frame.payload)
+                    ),
+                }
+            } else {
+                match process_frame(&frame) {
+                    Ok(StatsUpdate::Unknown { key, payload }) => {
+                        println!(
+                            "{prefix} UNKNOWN key {key:#04x}: {}",
+                            hex::encode_spaced(&payload)
+                        )
+                    }
+                    Ok(update) => println!("{prefix} {update:?}"),
+                    Err(err) => println!("{prefix} error: {err}"),
+                }
+            }
+        }
+    }
+    Ok(())
 }
 
-pub async fn exporter(adapter: &Adapter, device_name: &str, port: u16) -> anyhow::Result<()> {
-    let device = get_connected_device(adapter, device_name)
-        .await?
-        .context("Not connected to a device")?;
+#[derive(Serialize)]
+struct FlashlightOutput {
+    flashlight: FlashlightMode,
+}
 
-    let stream = setup_stats_stream(device).await?;
-    exporter::run(port, stream).await
+impl fmt::Display for FlashlightOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Current flashlight mode is: {}", self.flashlight)
+    }
+}
+
+#[derive(Serialize)]
+struct SettingOutput {
+    setting: Setting,
+    value: SettingValue,
+    /// Human-readable value, as in the table output
+    display: String,
+}
+
+impl SettingOutput {
+    fn new(setting: Setting, status: &Status) -> Self {
+        let value = status.setting(setting);
+        Self {
+            setting,
+            value: setting.typed_value(value),
+            display: setting.format_value(value),
+        }
+    }
+}
+
+impl fmt::Display for SettingOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is {}", self.setting, self.display)
+    }
 }
 
 pub async fn flashlight(
-    adapter: &Adapter,
-    device_name: &str,
+    transport: &impl Transport,
     mode: Option<FlashlightMode>,
+    retries: u32,
+    format: OutputFormat,
 ) -> anyhow::Result<()> {
-    let peripheral = get_connected_device(adapter, device_name)
-        .await?
-        .context("Not connected to a device")?;
+    let mut stream = log_parse_errors(setup_stats_stream(transport).await?);
 
     match mode {
         Some(mode) => {
-            peripheral
-                .discover_services()
+            write_confirmed(transport, &mut stream, Request::Flashlight(mode), retries)
                 .await
-                .context("Could not discover services")?;
-            let write_characteristic = peripheral
-                .characteristics()
-                .into_iter()
-                .find(|characteristic| characteristic.properties.contains(CharPropFlags::WRITE))
-                .context("Could not find a write characteristic")?;
-
-            let request = [0x13, 0x01, 0x00, mode as u8];
-            peripheral
-                .write(
-                    &write_characteristic,
-                    &request_to_buf(&request),
-                    WriteType::WithoutResponse,
-                )
-                .await?;
+                .with_context(|| format!("Could not set flashlight to {mode}"))?;
 
-            println!("Set flashlight to {mode}");
+            output::print(format, &FlashlightOutput { flashlight: mode })?;
         }
         None => {
-            let mut stream = setup_stats_stream(peripheral).await?;
             while let Some(update) = stream.next().await {
                 if let StatsUpdate::FlashlightStatus(mode) = update {
-                    println!("Current flashlight mode is: {mode}");
+                    output::print(format, &FlashlightOutput { flashlight: mode })?;
                     break;
                 }
             }
@@ -160,3 +646,408 @@ pub async fn flashlight(
     }
     Ok(())
 }
+
+pub async fn setting(
+    transport: &impl Transport,
+    setting: Setting,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let mut stream = log_parse_errors(setup_stats_stream(transport).await?);
+    let status = next_status(&mut stream).await?;
+    output::print(format, &SettingOutput::new(setting, &status))
+}
+
+async fn next_status(
+    stream: &mut (impl Stream<Item = StatsUpdate> + Unpin),
+) -> anyhow::Result<Status> {
+    let status = async {
+        while let Some(update) = stream.next().await {
+            if let StatsUpdate::Status(status) = update {
+                return Some(status);
+            }
+        }
+        None
+    };
+
+    tokio::time::timeout(RESPONSE_TIMEOUT, status)
+        .await
+        .context("Timed out waiting for a status update")?
+        .context("Notification stream ended")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        device_metrics, discover_device, export_device, export_metrics, flashlight, query_status,
+        raw, setting, setup_stats_stream, status, NotConfirmedError, SettingOutput,
+    };
+    use crate::{
+        exporter::{
+            self, collect,
+            energy::{EnergyFile, EnergyTotals},
+            gauge, Metrics,
+        },
+        output::{self, OutputFormat},
+        protocol::{
+            device_info::{FlashlightMode, Setting, Status},
+            frame::Frame,
+            request::Request,
+        },
+        transport::{mock::MockTransport, Discover, NotReconnectableError, MOCK_ADDRESS},
+    };
+    use prometheus::Registry;
+    use std::{
+        cell::{Cell, OnceCell, RefCell},
+        collections::HashMap,
+        time::Duration,
+    };
+
+    /// Device that is only found after a number of attempts
+    struct LateDevice {
+        attempts: Cell<u32>,
+        transport: MockTransport,
+    }
+
+    impl Discover for LateDevice {
+        type Transport = MockTransport;
+
+        fn filter(&self) -> String {
+            "Late".to_owned()
+        }
+
+        async fn discover(&self) -> anyhow::Result<Option<&MockTransport>> {
+            let attempts = self.attempts.get().saturating_sub(1);
+            self.attempts.set(attempts);
+            Ok((attempts == 0).then_some(&self.transport))
+        }
+    }
+
+    fn notification(frames: &[(u8, &[u8])]) -> Vec<u8> {
+        frames
+            .iter()
+            .flat_map(|(key, payload)| {
+                Frame {
+                    key: *key,
+                    payload: payload.to_vec(),
+                }
+                .encode()
+            })
+            .collect()
+    }
+
+    fn device_notifications() -> Vec<Vec<u8>> {
+        let mut capacity = vec![0; 28];
+        capacity[21..23].copy_from_slice(&5588u16.to_le_bytes());
+        capacity[23] = 90;
+
+        vec![
+            notification(&[(0x0f, &[0x00, 0x00, 0x03, 0x00]), (0x0b, &[0; 8])]),
+            notification(&[(0x16, &[0; 12]), (0x13, &[0x01]), (0x17, &[0; 6])]),
+            notification(&[(0x0c, &[0; 10]), (0x15, &[0x00])]),
+            notification(&[(0x04, &[0; 8]), (0x09, &capacity)]),
+        ]
+    }
+
+    #[tokio::test]
+    async fn status_from_notifications() {
+        let transport = MockTransport::new(device_notifications());
+        let info = query_status(&transport).await.unwrap();
+
+        let json = output::render(OutputFormat::Json, &info).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(1, value["schema_version"]);
+        assert_eq!(3, value["total_power"]["output"]);
+        assert_eq!("low", value["flashlight"]);
+        assert_eq!(false, value["status"]["ac_switch"]);
+        assert_eq!(90, value["capacity"]["battery_capacity_power"]);
+        assert_eq!(5588, value["capacity"]["discharge_time"]);
+    }
+
+    #[tokio::test]
+    async fn status_stream_ended() {
+        let transport = MockTransport::new(device_notifications().into_iter().take(2));
+        transport.close();
+        assert!(status(&transport, OutputFormat::Table).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn flashlight_confirmed() {
+        let transport = MockTransport::new([]).with_responder(|data| {
+            assert_eq!(Request::Flashlight(FlashlightMode::High).encode(), data);
+            vec![notification(&[(0x13, &[0x02])])]
+        });
+
+        flashlight(
+            &transport,
+            Some(FlashlightMode::High),
+            2,
+            OutputFormat::Table,
+        )
+        .await
+        .unwrap();
+        assert_eq!(1, transport.writes().len());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flashlight_not_confirmed() {
+        let transport =
+            MockTransport::new([]).with_responder(|_| vec![notification(&[(0x13, &[0x00])])]);
+
+        let err = flashlight(
+            &transport,
+            Some(FlashlightMode::High),
+            2,
+            OutputFormat::Table,
+        )
+        .await
+        .unwrap_err();
+        let err = err.downcast_ref::<NotConfirmedError>().unwrap();
+        assert_eq!(3, err.attempts);
+        assert_eq!(3, transport.writes().len());
+    }
+
+    #[tokio::test]
+    async fn setting_from_status() {
+        let transport = MockTransport::new(device_notifications());
+
+        setting(&transport, Setting::AcSwitch, OutputFormat::Json)
+            .await
+            .unwrap();
+        assert!(transport.writes().is_empty());
+    }
+
+    #[test]
+    fn setting_output() {
+        let status = Status {
+            ac_switch: true,
+            ac_frequency_hz: 1,
+            ..Default::default()
+        };
+        let json = |setting| {
+            let json = output::render(OutputFormat::Json, &SettingOutput::new(setting, &status));
+            serde_json::from_str::<serde_json::Value>(&json.unwrap()).unwrap()
+        };
+
+        let switch = json(Setting::AcSwitch);
+        assert_eq!("ac_switch", switch["setting"]);
+        assert_eq!(true, switch["value"]);
+        assert_eq!("on", switch["display"]);
+
+        let frequency = json(Setting::AcFrequency);
+        assert_eq!(1, frequency["value"]);
+        assert_eq!("unknown (1)", frequency["display"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn raw_request() {
+        let transport =
+            MockTransport::new([]).with_responder(|_| vec![notification(&[(0x20, &[0x01])])]);
+
+        raw(
+            &transport,
+            0x20,
+            &["01".to_owned(), "0203".to_owned()],
+            Duration::from_secs(3),
+        )
+        .await
+        .unwrap();
+
+        let expected = Frame {
+            key: 0x20,
+            payload: vec![0x01, 0x02, 0x03],
+        }
+        .encode_request();
+        assert_eq!(vec![expected], transport.writes());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn exporter_reconnects() {
+        let transport = MockTransport::new(device_notifications());
+        transport.close();
+
+        let registry = Registry::new();
+        let metrics = Metrics::new(&registry, "Mock", MOCK_ADDRESS).unwrap();
+        let _ = tokio::time::timeout(
+            Duration::from_secs(10),
+            export_metrics(&transport, &metrics),
+        )
+        .await;
+
+        assert_eq!(
+            Some(90.0),
+            gauge(&registry, "powerroam_battery_charge", &[])
+        );
+        assert_eq!(Some(0.0), gauge(&registry, "powerroam_connected", &[]));
+        // Reconnects after 1, 2 and 4 seconds
+        let reconnects = registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "powerroam_reconnects_total")
+            .unwrap();
+        assert_eq!(3.0, reconnects.get_metric()[0].get_counter().get_value());
+        assert_eq!(1, transport.writes().len());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn exporter_not_reconnectable() {
+        let transport = MockTransport::new(device_notifications()).not_reconnectable();
+        transport.close();
+
+        let registry = Registry::new();
+        let metrics = Metrics::new(&registry, "Mock", MOCK_ADDRESS).unwrap();
+        let err = tokio::time::timeout(
+            Duration::from_secs(10),
+            export_metrics(&transport, &metrics),
+        )
+        .await
+        .unwrap()
+        .unwrap_err();
+        assert!(err.is::<NotReconnectableError>());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn exporter_staleness() {
+        let transport = MockTransport::new(device_notifications());
+
+        let registry = Registry::new();
+        let metrics = Metrics::new(&registry, "Mock", MOCK_ADDRESS).unwrap();
+        let stream = setup_stats_stream(&transport).await.unwrap();
+        let up = || gauge(&registry, "powerroam_up", &[]);
+        assert_eq!(Some(0.0), up());
+
+        let stale_after = Duration::from_secs(30);
+        let _ = tokio::time::timeout(Duration::from_secs(10), collect(&metrics, stream)).await;
+        metrics.check_staleness(stale_after);
+        assert_eq!(Some(1.0), up());
+
+        let _ = tokio::time::timeout(
+            Duration::from_secs(30),
+            exporter::watch_staleness(&metrics, stale_after),
+        )
+        .await;
+        assert_eq!(Some(0.0), up());
+
+        let last_seen = registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == "powerroam_last_seen_timestamp_seconds")
+            .unwrap();
+        assert!(last_seen.get_metric().iter().any(|metric| metric
+            .get_label()
+            .iter()
+            .any(|pair| pair.get_value() == "capacity")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn exporter_missing_device() {
+        let registry = Registry::new();
+        let device = LateDevice {
+            attempts: Cell::new(3),
+            transport: MockTransport::new([]),
+        };
+        let connected = || gauge(&registry, "powerroam_connected", &[("device", "Late")]);
+
+        let discovery = discover_device(&registry, &device);
+        tokio::pin!(discovery);
+        // Looked for again after 1 and 2 seconds
+        let still_missing = tokio::time::timeout(Duration::from_secs(2), &mut discovery).await;
+        assert!(still_missing.is_err());
+        assert_eq!(Some(0.0), connected());
+
+        discovery.await.unwrap();
+        assert_eq!(None, connected());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn exporter_device_stopped() {
+        let transport = MockTransport::new(device_notifications()).not_reconnectable();
+        transport.close();
+        let saved = RefCell::new(EnergyFile::from([(
+            MOCK_ADDRESS.to_owned(),
+            EnergyTotals {
+                input: 3.0,
+                ..Default::default()
+            },
+        )]));
+
+        let registry = Registry::new();
+        let metrics = OnceCell::new();
+        let claimed = RefCell::new(HashMap::new());
+        let stale_after = Duration::from_secs(60);
+        export_device(
+            &registry,
+            &transport,
+            &metrics,
+            &saved,
+            &claimed,
+            stale_after,
+        )
+        .await;
+
+        assert_eq!(Some(0.0), gauge(&registry, "powerroam_up", &[]));
+        assert_eq!(Some(0.0), gauge(&registry, "powerroam_connected", &[]));
+        assert_eq!(3.0, metrics.get().unwrap().energy_totals().input);
+    }
+
+    #[tokio::test]
+    async fn exporter_duplicate_device() {
+        let registry = Registry::new();
+        let transport = MockTransport::new([]);
+        let claimed = RefCell::new(HashMap::new());
+
+        device_metrics(&registry, &transport, "Mock", &claimed)
+            .await
+            .unwrap();
+        let err = device_metrics(&registry, &transport, MOCK_ADDRESS, &claimed)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            format!(
+                "'Mock' and '{MOCK_ADDRESS}' both match the device {MOCK_ADDRESS}, pass each device only once"
+            ),
+            err.to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn exporter_metrics() {
+        let transport = MockTransport::new(device_notifications());
+        transport.close();
+
+        let registry = Registry::new();
+        let metrics = Metrics::new(&registry, "Mock", MOCK_ADDRESS).unwrap();
+        collect(&metrics, setup_stats_stream(&transport).await.unwrap()).await;
+
+        let value = |name, labels| gauge(&registry, name, labels).unwrap();
+        assert_eq!(90.0, value("powerroam_battery_charge", &[]));
+        assert_eq!(5588.0, value("powerroam_discharge_time", &[]));
+        assert_eq!(3.0, value("powerroam_total_output", &[]));
+        assert_eq!(1.0, value("powerroam_flashlight_mode", &[("mode", "low")]));
+        assert_eq!(0.0, value("powerroam_flashlight_mode", &[("mode", "off")]));
+        assert_eq!(1.0, value("powerroam_switch", &[("name", "key_voice")]));
+        assert_eq!(0.0, value("powerroam_switch", &[("name", "ac_switch")]));
+        assert_eq!(50.0, value("powerroam_ac_frequency_hz", &[]));
+        assert_eq!(
+            0.0,
+            value("powerroam_battery_power_watts", &[("battery", "one")])
+        );
+        assert_eq!(
+            0.0,
+            value("powerroam_inverter_power_watts", &[("inverter", "two")])
+        );
+
+        let families = registry.gather();
+        // 0x17 is part of every status notification and must not count as unknown
+        assert!(families
+            .iter()
+            .all(|family| family.get_name() != "powerroam_unknown_frames_total"));
+
+        let labels: Vec<_> = families[0].get_metric()[0]
+            .get_label()
+            .iter()
+            .map(|pair| (pair.get_name(), pair.get_value()))
+            .collect();
+        assert_eq!(vec![("address", MOCK_ADDRESS), ("device", "Mock")], labels);
+    }
+}
@@ -0,0 +1,302 @@
+use crate::protocol::device_info::{CapacityInfo, DeviceInfo, Setting, SwitchState, TotalPower};
+use std::fmt::{self, Write};
+use strum::IntoEnumIterator;
+
+/// Value of the charge and discharge times when they are not applicable
+const NO_TIME: u16 = u16::MAX;
+const LABEL_WIDTH: usize = 20;
+
+/// Settings that are shown in addition to the output switches
+pub fn settings() -> impl Iterator<Item = Setting> {
+    Setting::iter().filter(|setting| {
+        !matches!(
+            setting,
+            Setting::AcSwitch | Setting::DcSwitch | Setting::UsbSwitch
+        )
+    })
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let capacity = &self.capacity;
+        let total = &self.total_power;
+        let dc = &self.dc_power;
+        let status = &self.status;
+
+        let row = |f: &mut fmt::Formatter<'_>, label: &str, value: String| {
+            writeln!(f, "  {label:LABEL_WIDTH$}{value}")
+        };
+
+        writeln!(f, "Battery")?;
+        row(f, "Charge", format!("{}%", capacity.battery_capacity_power))?;
+        row(
+            f,
+            "Remaining",
+            format!("{} mAh", capacity.remaining_capacity),
+        )?;
+        row(f, "Time until full", format_minutes(capacity.charge_time))?;
+        row(
+            f,
+            "Time until empty",
+            format_minutes(capacity.discharge_time),
+        )?;
+        row(f, "Voltage", format_voltage(capacity))?;
+        row(f, "Cells", format_cells(capacity))?;
+        row(f, "Temperature", format_temperature(capacity))?;
+        row(f, "Low battery warning", yes_no(status.low_battery_warning))?;
+        row(f, "Battery health", yes_no(status.battery_health))?;
+
+        writeln!(f, "Power")?;
+        row(f, "Input", watts(total.input))?;
+        row(f, "Output", watts(total.output))?;
+        row(f, "Net", format_net_power(total))?;
+        row(f, "AC", watts(self.ac_power))?;
+        row(f, "DC total", watts(dc.total))?;
+        row(f, "USB-C1", watts(dc.type_c_one_power))?;
+        row(f, "USB-C2", watts(dc.type_c_two_power))?;
+        row(f, "USB-A1", watts(dc.usb_one_power))?;
+        row(f, "USB-A2", watts(dc.usb_two_power))?;
+        row(
+            f,
+            "Batteries",
+            format!(
+                "{} / {}",
+                watts(self.power.batteries_one_power),
+                watts(self.power.batteries_two_power)
+            ),
+        )?;
+        row(
+            f,
+            "Inverter",
+            format!(
+                "{} / {}",
+                watts(self.power.inverter_one_power),
+                watts(self.power.inverter_two_power)
+            ),
+        )?;
+
+        writeln!(f, "Outputs")?;
+        row(f, "AC", switch(status.ac_switch))?;
+        row(f, "DC", switch(status.dc_switch))?;
+        row(f, "USB", switch(status.usb_switch))?;
+        row(f, "Flashlight", self.flashlight.to_string())?;
+
+        writeln!(f, "Settings")?;
+        for setting in settings() {
+            row(
+                f,
+                &setting.to_string(),
+                setting.format_value(status.setting(setting)),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Short overview of the device state that fits on a small terminal
+pub fn compact_status(info: &DeviceInfo) -> String {
+    let capacity = &info.capacity;
+    let total = &info.total_power;
+    let dc = &info.dc_power;
+    let status = &info.status;
+
+    let remaining = if capacity.charge_time != NO_TIME {
+        format!("{} until full", format_minutes(capacity.charge_time))
+    } else if capacity.discharge_time != NO_TIME {
+        format!("{} until empty", format_minutes(capacity.discharge_time))
+    } else {
+        "idle".to_owned()
+    };
+
+    let mut output = String::new();
+    writeln!(
+        output,
+        "Battery: {}% ({remaining})",
+        capacity.battery_capacity_power
+    )
+    .unwrap();
+    writeln!(
+        output,
+        "Input: {}  Output: {}  Net: {}",
+        watts(total.input),
+        watts(total.output),
+        format_net_power(total)
+    )
+    .unwrap();
+    writeln!(
+        output,
+        "AC: {} [{}]  DC: {} [{}]  USB: [{}]",
+        watts(info.ac_power),
+        switch(status.ac_switch),
+        watts(dc.total),
+        switch(status.dc_switch),
+        switch(status.usb_switch),
+    )
+    .unwrap();
+    writeln!(
+        output,
+        "USB-C1: {}  USB-C2: {}  USB-A1: {}  USB-A2: {}",
+        watts(dc.type_c_one_power),
+        watts(dc.type_c_two_power),
+        watts(dc.usb_one_power),
+        watts(dc.usb_two_power)
+    )
+    .unwrap();
+    write!(output, "Flashlight: {}", info.flashlight).unwrap();
+    output
+}
+
+/// Formats a duration in minutes as hours and minutes, or n/a if the device doesn't report it
+pub fn format_minutes(minutes: u16) -> String {
+    if minutes == NO_TIME {
+        "n/a".to_owned()
+    } else {
+        format!("{}h {:02}m", minutes / 60, minutes % 60)
+    }
+}
+
+pub fn watts(value: u16) -> String {
+    format!("{value} W")
+}
+
+/// Input minus output, positive when the battery is charging
+pub fn format_net_power(total: &TotalPower) -> String {
+    let net = i32::from(total.input) - i32::from(total.output);
+    format!("{net:+} W")
+}
+
+pub fn format_voltage(capacity: &CapacityInfo) -> String {
+    format!("{:.1} V", f64::from(capacity.pack_voltage) / 10.0)
+}
+
+pub fn format_temperature(capacity: &CapacityInfo) -> String {
+    format!("{:.1} °C", f64::from(capacity.temperature) / 100.0)
+}
+
+fn format_cells(capacity: &CapacityInfo) -> String {
+    capacity
+        .cell_voltages
+        .iter()
+        .map(|millivolts| format!("{:.3}", f64::from(*millivolts) / 1000.0))
+        .collect::<Vec<_>>()
+        .join(" ")
+        + " V"
+}
+
+pub fn switch(enabled: bool) -> String {
+    SwitchState::from(enabled).to_string()
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compact_status, format_minutes};
+    use crate::protocol::device_info::{
+        CapacityInfo, DcPower, DeviceInfo, FlashlightMode, Power, Status, TotalPower,
+    };
+    use pretty_assertions::assert_eq;
+
+    fn device_info() -> DeviceInfo {
+        DeviceInfo {
+            power: Power {
+                batteries_one_power: 129,
+                batteries_two_power: 0,
+                inverter_one_power: 49,
+                inverter_two_power: 45,
+            },
+            total_power: TotalPower {
+                input: 0,
+                output: 129,
+            },
+            ac_power: 45,
+            flashlight: FlashlightMode::Low,
+            dc_power: DcPower {
+                type_c_one_power: 18,
+                type_c_two_power: 45,
+                usb_one_power: 5,
+                usb_two_power: 0,
+                total: 80,
+            },
+            status: Status {
+                ac_switch: true,
+                usb_switch: true,
+                battery_health: true,
+                key_voice: true,
+                ..Default::default()
+            },
+            electric_quantity_power: 0,
+            capacity: CapacityInfo {
+                charge_time: u16::MAX,
+                discharge_time: 312,
+                battery_capacity_power: 80,
+                pack_voltage: 233,
+                cell_voltages: [3339, 3351, 3331, 3344, 3336, 3348, 3333],
+                temperature: 3150,
+                remaining_capacity: 24223,
+            },
+        }
+    }
+
+    #[test]
+    fn full() {
+        let expected = "\
+Battery
+  Charge              80%
+  Remaining           24223 mAh
+  Time until full     n/a
+  Time until empty    5h 12m
+  Voltage             23.3 V
+  Cells               3.339 3.351 3.331 3.344 3.336 3.348 3.333 V
+  Temperature         31.5 °C
+  Low battery warning no
+  Battery health      yes
+Power
+  Input               0 W
+  Output              129 W
+  Net                 -129 W
+  AC                  45 W
+  DC total            80 W
+  USB-C1              18 W
+  USB-C2              45 W
+  USB-A1              5 W
+  USB-A2              0 W
+  Batteries           129 W / 0 W
+  Inverter            49 W / 45 W
+Outputs
+  AC                  on
+  DC                  off
+  USB                 on
+  Flashlight          Low
+Settings
+  AC frequency        50 Hz
+  AC turbo            off
+  Low-noise charging  off
+  Key beep            on
+  Warning voice       off
+  Child lock          off
+  Standby             off
+";
+        assert_eq!(expected, device_info().to_string());
+    }
+
+    #[test]
+    fn compact() {
+        let expected = "\
+Battery: 80% (5h 12m until empty)
+Input: 0 W  Output: 129 W  Net: -129 W
+AC: 45 W [on]  DC: 80 W [off]  USB: [on]
+USB-C1: 18 W  USB-C2: 45 W  USB-A1: 5 W  USB-A2: 0 W
+Flashlight: Low";
+        assert_eq!(expected, compact_status(&device_info()));
+    }
+
+    #[test]
+    fn minutes() {
+        assert_eq!("0h 05m", format_minutes(5));
+        assert_eq!("93h 10m", format_minutes(5590));
+        assert_eq!("n/a", format_minutes(u16::MAX));
+    }
+}
@@ -1,83 +1,809 @@
-use crate::protocol::notification::StatsUpdate;
+pub mod energy;
+
+use crate::protocol::{
+    device_info::{AcFrequency, DcPower, FlashlightMode, Status},
+    error::ParseError,
+    notification::StatsUpdate,
+};
 use anyhow::Context;
+use energy::{EnergyFile, EnergyTotals, Integrator};
 use futures::Stream;
 use futures::StreamExt;
+use prometheus::core::Collector;
 use prometheus::labels;
-use prometheus::opts;
-use prometheus::register_int_gauge;
-use prometheus::register_int_gauge_vec;
+use prometheus::Counter;
+use prometheus::CounterVec;
+use prometheus::Gauge;
+use prometheus::GaugeVec;
+use prometheus::IntCounter;
+use prometheus::IntCounterVec;
+use prometheus::IntGauge;
+use prometheus::IntGaugeVec;
+use prometheus::Opts;
+use prometheus::Registry;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use strum::IntoEnumIterator;
+use tokio::time::Instant;
 
-pub async fn run(
-    port: u16,
-    mut stream: impl Stream<Item = StatsUpdate> + Unpin,
-) -> anyhow::Result<()> {
+/// How often to check if the device metrics are stale
+const STALENESS_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the energy counters are written to the energy file
+const ENERGY_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+// Shared with the placeholder of devices that weren't found yet, both have to match
+const CONNECTED_NAME: &str = "powerroam_connected";
+const CONNECTED_HELP: &str = "Whether the exporter is receiving notifications from the device";
+
+/// Starts the metrics endpoint, which keeps running in the background
+pub fn start(port: u16) -> anyhow::Result<()> {
     let binding = format!("0.0.0.0:{port}")
         .parse()
         .context("Could not parse listen URL")?;
     println!("Exporter listening on '{binding}'");
     prometheus_exporter::start(binding)?;
+    Ok(())
+}
+
+/// Applies all updates from the stream to the metrics until it ends, returns if there were any updates
+pub async fn collect(
+    metrics: &Metrics,
+    mut stream: impl Stream<Item = Result<StatsUpdate, ParseError>> + Unpin,
+) -> bool {
+    let mut received = false;
+    while let Some(result) = stream.next().await {
+        metrics.update(result);
+        received = true;
+    }
+    received
+}
 
-    let battery_charge =
-        register_int_gauge!("powerroam_battery_charge", "Battery charge level").unwrap();
-    let charge_time =
-        register_int_gauge!("powerroam_charge_time", "Battery charge time in minutes").unwrap();
-    let discharge_time = register_int_gauge!(
-        "powerroam_discharge_time",
-        "Battery discharge time in minutes"
+/// Keeps `powerroam_up` at 0 while there were no updates for longer than `stale_after`
+pub async fn watch_staleness(metrics: &Metrics, stale_after: Duration) -> ! {
+    let mut interval = tokio::time::interval(STALENESS_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        metrics.check_staleness(stale_after);
+    }
+}
+
+/// Periodically writes the energy counters of all devices to the file so they survive restarts
+pub async fn persist_energy(
+    devices: &[OnceCell<Metrics>],
+    saved: &RefCell<EnergyFile>,
+    path: &Path,
+) -> ! {
+    let mut interval = tokio::time::interval(ENERGY_SAVE_INTERVAL);
+    // The first tick completes immediately, nothing was integrated yet
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if let Err(err) = save_energy(devices, saved, path) {
+            eprintln!("Could not save energy counters: {err:#}");
+        }
+    }
+}
+
+/// Merges the energy counters of the devices found so far into the saved totals and writes them to the file.
+///
+/// Totals of other devices are kept, e.g. of a device exported by another process or not found yet.
+pub fn save_energy(
+    devices: &[OnceCell<Metrics>],
+    saved: &RefCell<EnergyFile>,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let mut saved = saved.borrow_mut();
+    saved.extend(
+        devices
+            .iter()
+            .filter_map(OnceCell::get)
+            .map(|metrics| (metrics.address.clone(), metrics.energy_totals())),
+    );
+    energy::save(path, &saved)
+}
+
+/// Exports `powerroam_connected` as 0 for a device that wasn't found yet,
+/// labelled with the filter it's looked for with instead of its name
+pub fn register_missing(registry: &Registry, filter: &str) -> prometheus::Result<IntGauge> {
+    let labels = HashMap::from([
+        ("device".to_owned(), filter.to_owned()),
+        ("address".to_owned(), String::new()),
+    ]);
+    register(
+        registry,
+        IntGauge::with_opts(Opts::new(CONNECTED_NAME, CONNECTED_HELP).const_labels(labels))?,
     )
-    .unwrap();
-    let total_input = register_int_gauge!("powerroam_total_input", "Total input power").unwrap();
-    let total_output = register_int_gauge!("powerroam_total_output", "Total output power").unwrap();
-    let ac_output = register_int_gauge!("powerroam_ac_output", "Current AC output").unwrap();
-    let dc_output =
-        register_int_gauge_vec!(opts!("powerroam_dc_output", "Current DC output"), &["type"])
-            .unwrap();
-
-    while let Some(update) = stream.next().await {
+}
+
+/// Waits until the process is asked to stop with SIGINT or SIGTERM
+#[cfg(unix)]
+pub async fn shutdown_signal() -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => (),
+    }
+    Ok(())
+}
+
+/// Waits until the process is asked to stop with Ctrl-C
+#[cfg(not(unix))]
+pub async fn shutdown_signal() -> anyhow::Result<()> {
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
+/// Metrics of a single device, labelled with its name and address
+pub struct Metrics {
+    address: String,
+    battery_charge: IntGauge,
+    charge_time: IntGauge,
+    discharge_time: IntGauge,
+    total_input: IntGauge,
+    total_output: IntGauge,
+    ac_output: IntGauge,
+    dc_output: IntGaugeVec,
+    input_energy: Counter,
+    output_energy: Counter,
+    ac_output_energy: Counter,
+    dc_output_energy: CounterVec,
+    integrator: RefCell<Integrator>,
+    pack_voltage: IntGauge,
+    cell_voltage: IntGaugeVec,
+    battery_temperature: Gauge,
+    remaining_capacity: IntGauge,
+    battery_power: IntGaugeVec,
+    inverter_power: IntGaugeVec,
+    switch: IntGaugeVec,
+    ac_frequency: IntGauge,
+    /// Last unknown AC frequency value that was logged, to log it only once
+    unknown_ac_frequency: Cell<Option<u8>>,
+    flashlight_mode: IntGaugeVec,
+    electric_quantity: IntGauge,
+    info: IntGaugeVec,
+    connected: IntGauge,
+    reconnects: IntCounter,
+    last_update: Gauge,
+    last_seen: GaugeVec,
+    up: IntGauge,
+    last_received: Cell<Option<Instant>>,
+    unknown_frames: IntCounterVec,
+    parse_errors: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new(registry: &Registry, name: &str, address: &str) -> prometheus::Result<Self> {
+        let labels = HashMap::from([
+            ("device".to_owned(), name.to_owned()),
+            ("address".to_owned(), address.to_owned()),
+        ]);
+        let opts = |name: &str, help: &str| Opts::new(name, help).const_labels(labels.clone());
+
+        Ok(Self {
+            address: address.to_owned(),
+            battery_charge: register(
+                registry,
+                IntGauge::with_opts(opts("powerroam_battery_charge", "Battery charge level"))?,
+            )?,
+            charge_time: register(
+                registry,
+                IntGauge::with_opts(opts(
+                    "powerroam_charge_time",
+                    "Battery charge time in minutes",
+                ))?,
+            )?,
+            discharge_time: register(
+                registry,
+                IntGauge::with_opts(opts(
+                    "powerroam_discharge_time",
+                    "Battery discharge time in minutes",
+                ))?,
+            )?,
+            total_input: register(
+                registry,
+                IntGauge::with_opts(opts("powerroam_total_input", "Total input power"))?,
+            )?,
+            total_output: register(
+                registry,
+                IntGauge::with_opts(opts("powerroam_total_output", "Total output power"))?,
+            )?,
+            ac_output: register(
+                registry,
+                IntGauge::with_opts(opts("powerroam_ac_output", "Current AC output"))?,
+            )?,
+            dc_output: register(
+                registry,
+                IntGaugeVec::new(opts("powerroam_dc_output", "Current DC output"), &["type"])?,
+            )?,
+            input_energy: register(
+                registry,
+                Counter::with_opts(opts(
+                    "powerroam_input_energy_wh_total",
+                    "Energy received from all inputs in Wh",
+                ))?,
+            )?,
+            output_energy: register(
+                registry,
+                Counter::with_opts(opts(
+                    "powerroam_output_energy_wh_total",
+                    "Energy delivered on all outputs in Wh",
+                ))?,
+            )?,
+            ac_output_energy: register(
+                registry,
+                Counter::with_opts(opts(
+                    "powerroam_ac_output_energy_wh_total",
+                    "Energy delivered on the AC output in Wh",
+                ))?,
+            )?,
+            dc_output_energy: register(
+                registry,
+                CounterVec::new(
+                    opts(
+                        "powerroam_dc_output_energy_wh_total",
+                        "Energy delivered on the DC outputs in Wh",
+                    ),
+                    &["type"],
+                )?,
+            )?,
+            integrator: RefCell::new(Integrator::default()),
+            pack_voltage: register(
+                registry,
+                IntGauge::with_opts(opts(
+                    "powerroam_battery_voltage_millivolts",
+                    "Battery pack voltage in millivolts",
+                ))?,
+            )?,
+            cell_voltage: register(
+                registry,
+                IntGaugeVec::new(
+                    opts(
+                        "powerroam_cell_voltage_millivolts",
+                        "Battery cell voltage in millivolts",
+                    ),
+                    &["cell"],
+                )?,
+            )?,
+            battery_temperature: register(
+                registry,
+                Gauge::with_opts(opts(
+                    "powerroam_battery_temperature_celsius",
+                    "Battery temperature in degrees Celsius",
+                ))?,
+            )?,
+            remaining_capacity: register(
+                registry,
+                IntGauge::with_opts(opts(
+                    "powerroam_battery_remaining_capacity_mah",
+                    "Remaining battery capacity in mAh",
+                ))?,
+            )?,
+            battery_power: register(
+                registry,
+                IntGaugeVec::new(
+                    opts("powerroam_battery_power_watts", "Battery power in watts"),
+                    &["battery"],
+                )?,
+            )?,
+            inverter_power: register(
+                registry,
+                IntGaugeVec::new(
+                    opts("powerroam_inverter_power_watts", "Inverter power in watts"),
+                    &["inverter"],
+                )?,
+            )?,
+            switch: register(
+                registry,
+                IntGaugeVec::new(
+                    opts(
+                        "powerroam_switch",
+                        "State of the switches and flags reported in the status frame",
+                    ),
+                    &["name"],
+                )?,
+            )?,
+            ac_frequency: register(
+                registry,
+                IntGauge::with_opts(opts(
+                    "powerroam_ac_frequency_hz",
+                    "AC output frequency in Hz, 0 if the device reported an unknown value",
+                ))?,
+            )?,
+            unknown_ac_frequency: Cell::new(None),
+            flashlight_mode: register(
+                registry,
+                IntGaugeVec::new(
+                    opts(
+                        "powerroam_flashlight_mode",
+                        "Current flashlight mode, 1 for the active mode",
+                    ),
+                    &["mode"],
+                )?,
+            )?,
+            electric_quantity: register(
+                registry,
+                IntGauge::with_opts(opts(
+                    "powerroam_electric_quantity",
+                    "Electric quantity value reported by the device",
+                ))?,
+            )?,
+            info: register(
+                registry,
+                IntGaugeVec::new(
+                    opts(
+                        "powerroam_info",
+                        "Firmware and hardware version of the device",
+                    ),
+                    &["firmware", "hardware"],
+                )?,
+            )?,
+            connected: register(
+                registry,
+                IntGauge::with_opts(opts(CONNECTED_NAME, CONNECTED_HELP))?,
+            )?,
+            reconnects: register(
+                registry,
+                IntCounter::with_opts(opts(
+                    "powerroam_reconnects_total",
+                    "Number of times the connection to the device was restored",
+                ))?,
+            )?,
+            last_update: register(
+                registry,
+                Gauge::with_opts(opts(
+                    "powerroam_last_update_timestamp_seconds",
+                    "Unix time of the last successfully parsed update",
+                ))?,
+            )?,
+            last_seen: register(
+                registry,
+                GaugeVec::new(
+                    opts(
+                        "powerroam_last_seen_timestamp_seconds",
+                        "Unix time of the last update of each type",
+                    ),
+                    &["update"],
+                )?,
+            )?,
+            up: register(
+                registry,
+                IntGauge::with_opts(opts(
+                    "powerroam_up",
+                    "Whether the device metrics are up to date",
+                ))?,
+            )?,
+            last_received: Cell::new(None),
+            unknown_frames: register(
+                registry,
+                IntCounterVec::new(
+                    opts(
+                        "powerroam_unknown_frames_total",
+                        "Number of received frames with an unknown key",
+                    ),
+                    &["key"],
+                )?,
+            )?,
+            parse_errors: register(
+                registry,
+                IntCounterVec::new(
+                    opts(
+                        "powerroam_parse_errors_total",
+                        "Number of notification frames that could not be parsed",
+                    ),
+                    &["kind"],
+                )?,
+            )?,
+        })
+    }
+
+    pub fn set_connected(&self, connected: bool) {
+        self.connected.set(connected.into());
+        if !connected {
+            // Nothing is known about the power while disconnected
+            *self.integrator.borrow_mut() = Integrator::default();
+        }
+    }
+
+    /// Marks the device as down for good, after collecting from it has stopped
+    pub fn stop(&self) {
+        self.set_connected(false);
+        self.up.set(0);
+    }
+
+    pub fn reconnected(&self) {
+        self.reconnects.inc();
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Continues the energy counters from previously saved totals
+    pub fn restore_energy(&self, totals: &EnergyTotals) {
+        self.input_energy.inc_by(totals.input);
+        self.output_energy.inc_by(totals.output);
+        self.ac_output_energy.inc_by(totals.ac_output);
+        for (name, value) in &totals.dc_output {
+            self.dc_output_energy
+                .with(&labels! {
+                    "type" => name.as_str()
+                })
+                .inc_by(*value);
+        }
+    }
+
+    pub fn energy_totals(&self) -> EnergyTotals {
+        EnergyTotals {
+            input: self.input_energy.get(),
+            output: self.output_energy.get(),
+            ac_output: self.ac_output_energy.get(),
+            dc_output: self.dc_output_energy.collect()[0]
+                .get_metric()
+                .iter()
+                .filter_map(|metric| {
+                    let output = metric
+                        .get_label()
+                        .iter()
+                        .find(|pair| pair.get_name() == "type")?;
+                    Some((
+                        output.get_value().to_owned(),
+                        metric.get_counter().get_value(),
+                    ))
+                })
+                .collect(),
+        }
+    }
+
+    /// Sets `powerroam_up` to 0 if the last update is older than `stale_after`
+    pub fn check_staleness(&self, stale_after: Duration) {
+        let fresh = self
+            .last_received
+            .get()
+            .is_some_and(|received| received.elapsed() <= stale_after);
+        self.up.set(fresh.into());
+    }
+
+    pub fn update(&self, result: Result<StatsUpdate, ParseError>) {
+        let update = match result {
+            Ok(update) => update,
+            Err(err) => {
+                self.parse_errors
+                    .with(&labels! {
+                        "kind" => err.kind()
+                    })
+                    .inc();
+                eprintln!("{}: Could not parse notification: {err}", self.address);
+                return;
+            }
+        };
+
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        self.last_update.set(now.as_secs_f64());
+        self.last_seen
+            .with(&labels! {
+                "update" => update.kind()
+            })
+            .set(now.as_secs_f64());
+        let received = Instant::now();
+        self.last_received.set(Some(received));
+        // Only counts as connected once the device actually sends something
+        self.set_connected(true);
+        let mut integrator = self.integrator.borrow_mut();
+        self.up.set(1);
+
         use StatsUpdate::*;
         match update {
-            AcPower(value) => ac_output.set(value.into()),
+            AcPower(value) => {
+                self.ac_output.set(value.into());
+                self.ac_output_energy
+                    .inc_by(integrator.add("ac_output", value, received));
+            }
             DcPower(power) => {
-                for (name, value) in [
-                    ("total", power.total),
-                    ("type_c_one", power.type_c_one_power),
-                    ("type_c_two", power.type_c_two_power),
-                    ("usb_one", power.usb_one_power),
-                    ("usb_two", power.usb_two_power),
-                ] {
-                    dc_output
-                        .with(&labels! {
-                            "type" => name
-                        })
-                        .set(value.into());
+                for (name, value) in dc_outputs(&power) {
+                    let labels = labels! {
+                        "type" => name
+                    };
+                    self.dc_output.with(&labels).set(value.into());
+                    self.dc_output_energy
+                        .with(&labels)
+                        .inc_by(integrator.add(name, value, received));
                 }
             }
             TotalPower(total) => {
-                total_input.set(total.input.into());
-                total_output.set(total.output.into());
+                self.total_input.set(total.input.into());
+                self.total_output.set(total.output.into());
+                self.input_energy
+                    .inc_by(integrator.add("input", total.input, received));
+                self.output_energy
+                    .inc_by(integrator.add("output", total.output, received));
             }
             Capacity(capacity) => {
-                battery_charge.set(capacity.battery_capacity_power.into());
+                self.battery_charge
+                    .set(capacity.battery_capacity_power.into());
 
                 let charge = if capacity.charge_time == u16::MAX {
                     0
                 } else {
                     capacity.charge_time as i64
                 };
-                charge_time.set(charge);
+                self.charge_time.set(charge);
 
                 let discharge = if capacity.discharge_time == u16::MAX {
                     0
                 } else {
                     capacity.discharge_time as i64
                 };
-                discharge_time.set(discharge);
+                self.discharge_time.set(discharge);
+
+                self.pack_voltage
+                    .set(i64::from(capacity.pack_voltage) * 100);
+                for (i, value) in capacity.cell_voltages.into_iter().enumerate() {
+                    self.cell_voltage
+                        .with_label_values(&[&(i + 1).to_string()])
+                        .set(value.into());
+                }
+                self.battery_temperature
+                    .set(f64::from(capacity.temperature) / 100.0);
+                self.remaining_capacity
+                    .set(capacity.remaining_capacity.into());
             }
-            _ => (),
+            Power(power) => {
+                for (name, value) in [
+                    ("one", power.batteries_one_power),
+                    ("two", power.batteries_two_power),
+                ] {
+                    self.battery_power
+                        .with_label_values(&[name])
+                        .set(value.into());
+                }
+                for (name, value) in [
+                    ("one", power.inverter_one_power),
+                    ("two", power.inverter_two_power),
+                ] {
+                    self.inverter_power
+                        .with_label_values(&[name])
+                        .set(value.into());
+                }
+            }
+            Status(status) => {
+                for (name, value) in switches(&status) {
+                    self.switch.with_label_values(&[name]).set(value.into());
+                }
+                let frequency = AcFrequency::from_repr(status.ac_frequency_hz);
+                self.ac_frequency.set(match frequency {
+                    Some(AcFrequency::Hz50) => 50,
+                    None => 0,
+                });
+
+                let unknown = frequency.is_none().then_some(status.ac_frequency_hz);
+                if unknown.is_some() && self.unknown_ac_frequency.get() != unknown {
+                    eprintln!(
+                        "{}: Unknown AC frequency value {}",
+                        self.address, status.ac_frequency_hz
+                    );
+                }
+                self.unknown_ac_frequency.set(unknown);
+            }
+            FlashlightStatus(current) => {
+                for mode in FlashlightMode::iter() {
+                    self.flashlight_mode
+                        .with_label_values(&[&mode.to_string().to_lowercase()])
+                        .set((mode == current).into());
+                }
+            }
+            ElectricQuantityPower(value) => self.electric_quantity.set(value.into()),
+            Version(version) => {
+                self.info.reset();
+                self.info
+                    .with_label_values(&[&version.firmware, &version.hardware])
+                    .set(1);
+            }
+            Key17(_) => (),
+            Unknown { key, .. } => self
+                .unknown_frames
+                .with_label_values(&[&format!("{key:#04x}")])
+                .inc(),
         }
     }
+}
 
-    println!("Notification stream ended");
+fn switches(status: &Status) -> [(&'static str, bool); 11] {
+    [
+        ("low_noise", status.low_noise),
+        ("low_battery_warning", status.low_battery_warning),
+        ("usb_switch", status.usb_switch),
+        ("dc_switch", status.dc_switch),
+        ("warning_voice", status.warning_voice),
+        ("ac_turbo", status.ac_turbo),
+        ("ac_switch", status.ac_switch),
+        ("battery_health", status.battery_health),
+        ("locking", status.locking),
+        ("key_voice", status.key_voice),
+        ("standby", status.standby),
+    ]
+}
 
-    Ok(())
+fn dc_outputs(power: &DcPower) -> [(&'static str, u16); 5] {
+    [
+        ("total", power.total),
+        ("type_c_one", power.type_c_one_power),
+        ("type_c_two", power.type_c_two_power),
+        ("usb_one", power.usb_one_power),
+        ("usb_two", power.usb_two_power),
+    ]
+}
+
+fn register<C: Collector + Clone + 'static>(
+    registry: &Registry,
+    collector: C,
+) -> prometheus::Result<C> {
+    registry.register(Box::new(collector.clone()))?;
+    Ok(collector)
+}
+
+/// Value of the gauge that has all the given labels, `None` if there is no such gauge
+#[cfg(test)]
+pub fn gauge(registry: &Registry, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+    let family = registry
+        .gather()
+        .into_iter()
+        .find(|family| family.get_name() == name)?;
+    family
+        .get_metric()
+        .iter()
+        .find(|metric| {
+            labels.iter().all(|(name, value)| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|pair| pair.get_name() == *name && pair.get_value() == *value)
+            })
+        })
+        .map(|metric| metric.get_gauge().get_value())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        energy::{self, EnergyFile, EnergyTotals},
+        gauge, save_energy, Metrics,
+    };
+    use crate::{
+        protocol::{
+            device_info::{DcPower, Status, TotalPower},
+            notification::StatsUpdate,
+        },
+        transport::MOCK_ADDRESS,
+    };
+    use prometheus::Registry;
+    use std::{
+        cell::{OnceCell, RefCell},
+        time::Duration,
+    };
+
+    #[test]
+    fn connected_on_update() {
+        let registry = Registry::new();
+        let metrics = Metrics::new(&registry, "Mock", MOCK_ADDRESS).unwrap();
+        assert_eq!(Some(0.0), gauge(&registry, "powerroam_connected", &[]));
+
+        metrics.update(Ok(StatsUpdate::AcPower(0)));
+        assert_eq!(Some(1.0), gauge(&registry, "powerroam_connected", &[]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn energy() {
+        let registry = Registry::new();
+        let metrics = Metrics::new(&registry, "Mock", MOCK_ADDRESS).unwrap();
+        metrics.restore_energy(&EnergyTotals {
+            input: 10.0,
+            ..Default::default()
+        });
+
+        let total_power = |input, output| Ok(StatsUpdate::TotalPower(TotalPower { input, output }));
+        metrics.update(total_power(200, 100));
+        tokio::time::advance(Duration::from_secs(36)).await;
+        metrics.update(total_power(0, 100));
+        metrics.update(Ok(StatsUpdate::DcPower(DcPower {
+            usb_one_power: 10,
+            ..Default::default()
+        })));
+        // Not integrated because the readings are too far apart
+        tokio::time::advance(Duration::from_secs(360)).await;
+        metrics.update(Ok(StatsUpdate::DcPower(DcPower::default())));
+
+        let totals = metrics.energy_totals();
+        assert_eq!(12.0, totals.input);
+        assert_eq!(1.0, totals.output);
+        assert_eq!(Some(&0.0), totals.dc_output.get("usb_one"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn energy_disconnected() {
+        let registry = Registry::new();
+        let metrics = Metrics::new(&registry, "Mock", MOCK_ADDRESS).unwrap();
+
+        let total_power = |input| Ok(StatsUpdate::TotalPower(TotalPower { input, output: 0 }));
+        metrics.update(total_power(100));
+        // The reading from before the disconnect must not be integrated
+        metrics.set_connected(false);
+        tokio::time::advance(Duration::from_secs(36)).await;
+        metrics.update(total_power(100));
+        tokio::time::advance(Duration::from_secs(36)).await;
+        metrics.update(total_power(0));
+
+        assert_eq!(1.0, metrics.energy_totals().input);
+    }
+
+    #[test]
+    fn devices() {
+        let registry = Registry::new();
+        let first = Metrics::new(&registry, "Mock", "00:00:00:00:00:01").unwrap();
+        let second = Metrics::new(&registry, "Mock", "00:00:00:00:00:02").unwrap();
+        first.set_connected(true);
+        second.set_connected(false);
+
+        let connected = |address| gauge(&registry, "powerroam_connected", &[("address", address)]);
+        assert_eq!(Some(1.0), connected("00:00:00:00:00:01"));
+        assert_eq!(Some(0.0), connected("00:00:00:00:00:02"));
+    }
+
+    #[test]
+    fn energy_merge() {
+        let path = std::env::temp_dir().join(format!(
+            "proam-cli-energy-merge-{}.json",
+            std::process::id()
+        ));
+        let other = EnergyTotals {
+            input: 5.0,
+            ..Default::default()
+        };
+        let saved = RefCell::new(EnergyFile::from([("AA:BB:CC:DD:EE:FF".to_owned(), other)]));
+
+        let registry = Registry::new();
+        let metrics = Metrics::new(&registry, "Mock", MOCK_ADDRESS).unwrap();
+        metrics.restore_energy(&EnergyTotals {
+            output: 2.0,
+            ..Default::default()
+        });
+        let devices = [OnceCell::from(metrics), OnceCell::new()];
+
+        save_energy(&devices, &saved, &path).unwrap();
+        let loaded = energy::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Totals of devices that are not exported by this process are kept
+        assert_eq!(5.0, loaded["AA:BB:CC:DD:EE:FF"].input);
+        assert_eq!(2.0, loaded[MOCK_ADDRESS].output);
+    }
+
+    #[test]
+    fn unknown_ac_frequency() {
+        let registry = Registry::new();
+        let metrics = Metrics::new(&registry, "Mock", MOCK_ADDRESS).unwrap();
+        let status = |ac_frequency_hz| {
+            Ok(StatsUpdate::Status(Status {
+                ac_frequency_hz,
+                ..Default::default()
+            }))
+        };
+
+        metrics.update(status(0));
+        assert_eq!(
+            Some(50.0),
+            gauge(&registry, "powerroam_ac_frequency_hz", &[])
+        );
+        // The previous value must not stay around, 1 is not known to be 60 Hz
+        metrics.update(status(1));
+        assert_eq!(
+            Some(0.0),
+            gauge(&registry, "powerroam_ac_frequency_hz", &[])
+        );
+    }
 }
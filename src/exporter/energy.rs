@@ -0,0 +1,142 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    collections::HashMap,
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// Readings further apart than this are not integrated, e.g. after a disconnect
+const MAX_GAP: Duration = Duration::from_secs(60);
+
+/// Energy counters of each device, keyed by address
+pub type EnergyFile = BTreeMap<String, EnergyTotals>;
+
+/// Energy counters of a device in Wh
+#[derive(Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct EnergyTotals {
+    pub input: f64,
+    pub output: f64,
+    pub ac_output: f64,
+    /// Keyed by the same type label as `powerroam_dc_output`
+    pub dc_output: BTreeMap<String, f64>,
+}
+
+/// Integrates power readings into energy, assuming the power stays constant until the next reading
+#[derive(Default)]
+pub struct Integrator {
+    last: HashMap<&'static str, (Instant, u16)>,
+}
+
+impl Integrator {
+    /// Records a reading and returns the energy in Wh since the previous reading of the channel
+    pub fn add(&mut self, channel: &'static str, watts: u16, now: Instant) -> f64 {
+        let Some((time, previous)) = self.last.insert(channel, (now, watts)) else {
+            return 0.0;
+        };
+
+        let elapsed = now.duration_since(time);
+        if elapsed > MAX_GAP {
+            return 0.0;
+        }
+        f64::from(previous) * elapsed.as_secs_f64() / 3600.0
+    }
+}
+
+/// Reads the energy file, returns no totals if it doesn't exist yet
+pub fn load(path: &Path) -> anyhow::Result<EnergyFile> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(EnergyFile::default()),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Could not read '{}'", path.display()))
+        }
+    };
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Invalid energy file '{}'", path.display()))
+}
+
+/// Writes the energy file through a temporary file that is synced and renamed over it,
+/// so a crash or power loss leaves either the old or the new contents behind
+pub fn save(path: &Path, totals: &EnergyFile) -> anyhow::Result<()> {
+    let temporary = path.with_extension("tmp");
+    let write = || -> std::io::Result<()> {
+        let mut file = File::create(&temporary)?;
+        file.write_all(serde_json::to_string_pretty(totals)?.as_bytes())?;
+        file.sync_all()
+    };
+    write().with_context(|| format!("Could not write '{}'", temporary.display()))?;
+    fs::rename(&temporary, path)
+        .with_context(|| format!("Could not replace '{}'", path.display()))?;
+    sync_parent(path)
+        .with_context(|| format!("Could not sync the directory of '{}'", path.display()))
+}
+
+/// Makes the rename durable, which needs the directory entry to be synced
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> std::io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, save, EnergyFile, EnergyTotals, Integrator};
+    use pretty_assertions::assert_eq;
+    use std::{collections::BTreeMap, time::Duration};
+    use tokio::time::Instant;
+
+    #[test]
+    fn integrate() {
+        let start = Instant::now();
+        let mut integrator = Integrator::default();
+        assert_eq!(0.0, integrator.add("input", 100, start));
+        assert_eq!(
+            1.0,
+            integrator.add("input", 200, start + Duration::from_secs(36))
+        );
+        assert_eq!(0.0, integrator.add("output", 50, start));
+        assert_eq!(
+            2.0,
+            integrator.add("input", 0, start + Duration::from_secs(72))
+        );
+        // Too long since the previous reading
+        assert_eq!(
+            0.0,
+            integrator.add("output", 50, start + Duration::from_secs(3600))
+        );
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path =
+            std::env::temp_dir().join(format!("proam-cli-energy-{}.json", std::process::id()));
+        assert_eq!(EnergyFile::default(), load(&path).unwrap());
+
+        let totals = EnergyFile::from([(
+            "AA:BB:CC:DD:EE:FF".to_owned(),
+            EnergyTotals {
+                input: 12.5,
+                output: 3.25,
+                ac_output: 1.0,
+                dc_output: BTreeMap::from([("usb_one".to_owned(), 0.5)]),
+            },
+        )]);
+        save(&path, &totals).unwrap();
+        let loaded = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(totals, loaded);
+    }
+}
@@ -0,0 +1,49 @@
+use anyhow::Context;
+
+pub fn encode(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Formats the data with a space between bytes, for easier reading
+pub fn encode_spaced(data: &[u8]) -> String {
+    data.iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parses a hex string, ignoring whitespace between bytes
+pub fn decode(text: &str) -> anyhow::Result<Vec<u8>> {
+    let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.len() % 2 == 1 {
+        anyhow::bail!("Odd number of hex digits");
+    }
+
+    digits
+        .chunks_exact(2)
+        .map(|pair| {
+            let byte: String = pair.iter().collect();
+            u8::from_str_radix(&byte, 16).with_context(|| format!("Invalid hex byte '{byte}'"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, encode_spaced};
+
+    #[test]
+    fn round_trip() {
+        let data = [0x5a, 0xa5, 0x00, 0x0f];
+        assert_eq!("5aa5000f", encode(&data));
+        assert_eq!("5a a5 00 0f", encode_spaced(&data));
+        assert_eq!(data.to_vec(), decode("5aa5000f").unwrap());
+        assert_eq!(data.to_vec(), decode("5a a5 00 0F").unwrap());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(decode("5aa").is_err());
+        assert!(decode("zz").is_err());
+    }
+}
@@ -1,36 +1,105 @@
 mod args;
+mod btsnoop;
 mod commands;
+mod display;
 mod exporter;
+mod hex;
+mod output;
 mod protocol;
+mod transport;
+mod tui;
 
 use anyhow::Context;
-use args::Args;
+use args::{Args, ExporterArgs};
 use btleplug::{
     api::Manager as _,
     platform::{Adapter, Manager},
 };
 use clap::Parser;
+use commands::find_device;
+use std::{slice, time::Duration};
+use transport::{
+    bluetooth::BluetoothDiscovery, replay::ReplayTransport, simulator::SimulatedPowerRoam,
+    Discover, Transport,
+};
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
+    if let args::Command::DecodeBtsnoop { file } = &args.cmd {
+        return commands::decode_btsnoop(file);
+    }
+
+    if args.simulate {
+        return run(&SimulatedPowerRoam::default(), args).await;
+    }
+    if let Some(path) = &args.replay {
+        let transport = ReplayTransport::open(path, args.original_speed)?;
+        return run(&transport, args).await;
+    }
+
     let adapter = init_adapter()
         .await
         .context("Could not initialize bluetooth")?;
 
+    if let args::Command::Connect = args.cmd {
+        return commands::connect(&adapter, &args.device_name).await;
+    }
+
+    if let args::Command::Exporter(options) = &args.cmd {
+        let filters = if options.devices.is_empty() {
+            slice::from_ref(&args.device_name)
+        } else {
+            &options.devices
+        };
+        let devices: Vec<_> = filters
+            .iter()
+            .map(|filter| BluetoothDiscovery::new(&adapter, filter))
+            .collect();
+        return exporter(&devices, options).await;
+    }
+
+    let device = find_device(&adapter, &args.device_name).await?;
+    run(&device, args).await
+}
+
+async fn run(transport: &impl Transport, args: Args) -> anyhow::Result<()> {
     match args.cmd {
-        args::Command::Status => commands::status(&adapter, &args.device_name).await,
-        args::Command::Connect => commands::connect(&adapter, &args.device_name).await,
-        args::Command::Exporter { port } => {
-            commands::exporter(&adapter, &args.device_name, port).await
+        args::Command::Status => commands::status(transport, args.format).await,
+        args::Command::Connect => anyhow::bail!("Cannot connect when not using bluetooth"),
+        args::Command::Raw { key, payload, wait } => {
+            commands::raw(transport, key, &payload, Duration::from_secs(wait)).await
         }
+        args::Command::Watch => commands::watch(transport).await,
+        args::Command::Dashboard => commands::dashboard(transport, args.retries).await,
+        args::Command::Sniff => commands::sniff(transport).await,
+        args::Command::DecodeBtsnoop { .. } => unreachable!("Handled before connecting"),
+        args::Command::Record { out } => commands::record(transport, &out).await,
+        args::Command::Info => commands::info(transport, args.retries, args.format).await,
+        args::Command::Output { output } => {
+            commands::setting(transport, output.into(), args.format).await
+        }
+        args::Command::Settings { setting } => {
+            commands::setting(transport, setting.into(), args.format).await
+        }
+        args::Command::Exporter(options) => exporter(slice::from_ref(transport), &options).await,
         args::Command::Flashlight { mode } => {
-            commands::flashlight(&adapter, &args.device_name, mode).await
+            commands::flashlight(transport, mode, args.retries, args.format).await
         }
     }
 }
 
+async fn exporter(devices: &[impl Discover], options: &ExporterArgs) -> anyhow::Result<()> {
+    commands::exporter(
+        devices,
+        options.port,
+        Duration::from_secs(options.stale_after),
+        options.energy_file.as_deref(),
+    )
+    .await
+}
+
 async fn init_adapter() -> anyhow::Result<Adapter> {
     let manager = Manager::new()
         .await
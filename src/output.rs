@@ -0,0 +1,125 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fmt::Display;
+
+/// Version of the JSON and YAML output structure, see `docs/json-output.md`.
+///
+/// Incremented whenever a field is removed, renamed or changes its meaning.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable output
+    #[default]
+    Table,
+    Json,
+    Yaml,
+}
+
+#[derive(Serialize)]
+struct Versioned<'a, T> {
+    schema_version: u32,
+    #[serde(flatten)]
+    data: &'a T,
+}
+
+/// Prints the value in the given format
+pub fn print<T: Serialize + Display>(format: OutputFormat, value: &T) -> anyhow::Result<()> {
+    println!("{}", render(format, value)?.trim_end());
+    Ok(())
+}
+
+pub fn render<T: Serialize + Display>(format: OutputFormat, value: &T) -> anyhow::Result<String> {
+    let versioned = Versioned {
+        schema_version: SCHEMA_VERSION,
+        data: value,
+    };
+
+    Ok(match format {
+        OutputFormat::Table => value.to_string(),
+        OutputFormat::Json => serde_json::to_string_pretty(&versioned)?,
+        OutputFormat::Yaml => serde_norway::to_string(&versioned)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, OutputFormat, SCHEMA_VERSION};
+    use pretty_assertions::assert_eq;
+    use serde::Serialize;
+    use std::fmt;
+
+    #[derive(Serialize)]
+    struct Example {
+        name: &'static str,
+        values: [u16; 2],
+        nested: Nested,
+    }
+
+    #[derive(Serialize)]
+    struct Nested {
+        enabled: bool,
+    }
+
+    impl fmt::Display for Example {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}: {:?}", self.name, self.values)
+        }
+    }
+
+    fn example() -> Example {
+        Example {
+            name: "test",
+            values: [1, 2],
+            nested: Nested { enabled: true },
+        }
+    }
+
+    #[test]
+    fn table() {
+        assert_eq!(
+            "test: [1, 2]",
+            render(OutputFormat::Table, &example()).unwrap()
+        );
+    }
+
+    #[test]
+    fn json() {
+        let expected = r#"{
+  "schema_version": 1,
+  "name": "test",
+  "values": [
+    1,
+    2
+  ],
+  "nested": {
+    "enabled": true
+  }
+}"#;
+        assert_eq!(expected, render(OutputFormat::Json, &example()).unwrap());
+    }
+
+    #[test]
+    fn schema_file() {
+        let schema: serde_json::Value =
+            serde_json::from_str(include_str!("../docs/output.schema.json")).unwrap();
+        assert_eq!(
+            SCHEMA_VERSION,
+            schema["properties"]["schema_version"]["const"]
+        );
+    }
+
+    #[test]
+    fn yaml() {
+        let expected = "\
+schema_version: 1
+name: test
+values:
+- 1
+- 2
+nested:
+  enabled: true
+";
+        assert_eq!(expected, render(OutputFormat::Yaml, &example()).unwrap());
+    }
+}
@@ -1,5 +1,8 @@
 pub mod device_info;
+pub mod error;
+pub mod frame;
 pub mod notification;
 pub mod request;
 
 const SEPARATOR_SEQUENCE: [u8; 4] = [0x5a, 0xa5, 0xc0, 0xa1];
+const REQUEST_SEPARATOR_SEQUENCE: [u8; 4] = [0x5a, 0xa5, 0xa1, 0xc0];
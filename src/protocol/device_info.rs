@@ -1,10 +1,11 @@
-use clap::Subcommand;
+use clap::{Subcommand, ValueEnum};
 use futures::{Stream, StreamExt};
-use strum::{Display, FromRepr};
+use serde::Serialize;
+use strum::{Display, EnumIter, FromRepr};
 
 use super::notification::StatsUpdate;
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Default, Clone, Copy, Serialize)]
 pub struct DeviceInfo {
     pub power: Power,
     pub total_power: TotalPower,
@@ -16,7 +17,7 @@ pub struct DeviceInfo {
     pub capacity: CapacityInfo,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Default, Clone, Copy, Serialize)]
 pub struct Power {
     pub batteries_one_power: u16,
     pub batteries_two_power: u16,
@@ -24,21 +25,27 @@ pub struct Power {
     pub inverter_two_power: u16,
 }
 
-// impl DeviceInfo {
-//     pub fn apply_update(&mut self, update: StatsUpdate) {
-//         match update {
-//             StatsUpdate::TotalPower(v) => self.total_power = v,
-//             StatsUpdate::AcPower(v) => self.ac_power = v,
-//             StatsUpdate::FlashlightStatus(v) => self.flashlight = v,
-//             StatsUpdate::DcPower(v) => self.dc_power = v,
-//             StatsUpdate::Status(v) => self.status = v,
-//             StatsUpdate::ElectricQuantityPower(v) => self.electric_quantity_power = v,
-//             StatsUpdate::Capacity(v) => self.capacity = v,
-//         }
-//     }
-// }
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy, FromRepr, Default, Display, Subcommand)]
+impl DeviceInfo {
+    /// Replaces the part of the info that the update describes
+    pub fn apply_update(&mut self, update: StatsUpdate) {
+        match update {
+            StatsUpdate::Power(v) => self.power = v,
+            StatsUpdate::TotalPower(v) => self.total_power = v,
+            StatsUpdate::AcPower(v) => self.ac_power = v,
+            StatsUpdate::FlashlightStatus(v) => self.flashlight = v,
+            StatsUpdate::DcPower(v) => self.dc_power = v,
+            StatsUpdate::Status(v) => self.status = v,
+            StatsUpdate::ElectricQuantityPower(v) => self.electric_quantity_power = v,
+            StatsUpdate::Capacity(v) => self.capacity = v,
+            StatsUpdate::Key17(_) | StatsUpdate::Version(_) | StatsUpdate::Unknown { .. } => (),
+        }
+    }
+}
+
+#[derive(
+    Debug, PartialEq, Eq, Clone, Copy, FromRepr, EnumIter, Default, Display, Subcommand, Serialize,
+)]
+#[serde(rename_all = "lowercase")]
 pub enum FlashlightMode {
     #[default]
     Off = 0,
@@ -49,13 +56,117 @@ pub enum FlashlightMode {
     Sos = 4,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Display, ValueEnum)]
+pub enum OutputSwitch {
+    #[strum(serialize = "AC")]
+    Ac,
+    #[strum(serialize = "DC")]
+    Dc,
+    #[strum(serialize = "USB")]
+    Usb,
+}
+
+/// AC output frequency as it is encoded in the status frame.
+///
+/// Captures so far only contain 0 from a 50 Hz unit, other values are shown as unknown
+/// until a 60 Hz unit has been captured.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, FromRepr, Display)]
+#[repr(u8)]
+pub enum AcFrequency {
+    #[strum(serialize = "50 Hz")]
+    Hz50 = 0,
+}
+
+/// Device setting that is reported in the status frame, in the order it is displayed
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Display, EnumIter, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Setting {
+    #[strum(serialize = "AC output")]
+    AcSwitch,
+    #[strum(serialize = "DC output")]
+    DcSwitch,
+    #[strum(serialize = "USB output")]
+    UsbSwitch,
+    #[strum(serialize = "AC frequency")]
+    AcFrequency,
+    #[strum(serialize = "AC turbo")]
+    AcTurbo,
+    #[strum(serialize = "Low-noise charging")]
+    LowNoise,
+    #[strum(serialize = "Key beep")]
+    KeyVoice,
+    #[strum(serialize = "Warning voice")]
+    WarningVoice,
+    #[strum(serialize = "Child lock")]
+    Locking,
+    #[strum(serialize = "Standby")]
+    Standby,
+}
+
+impl Setting {
+    /// Converts a value encoded the way it is in the status frame
+    pub fn typed_value(self, value: u8) -> SettingValue {
+        match self {
+            Setting::AcFrequency => SettingValue::Raw(value),
+            Setting::KeyVoice => SettingValue::Switch(value == 0),
+            _ => SettingValue::Switch(value != 0),
+        }
+    }
+
+    pub fn format_value(self, value: u8) -> String {
+        match self.typed_value(value) {
+            SettingValue::Switch(enabled) => SwitchState::from(enabled).to_string(),
+            SettingValue::Raw(value) => match AcFrequency::from_repr(value) {
+                Some(frequency) => frequency.to_string(),
+                None => format!("unknown ({value})"),
+            },
+        }
+    }
+}
+
+impl From<OutputSwitch> for Setting {
+    fn from(output: OutputSwitch) -> Self {
+        match output {
+            OutputSwitch::Ac => Setting::AcSwitch,
+            OutputSwitch::Dc => Setting::DcSwitch,
+            OutputSwitch::Usb => Setting::UsbSwitch,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+#[serde(untagged)]
+pub enum SettingValue {
+    /// `true` if the switch is on
+    Switch(bool),
+    /// Value as it is sent by the device, used for the AC frequency
+    Raw(u8),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum SwitchState {
+    On,
+    Off,
+}
+
+impl From<bool> for SwitchState {
+    fn from(value: bool) -> Self {
+        if value {
+            SwitchState::On
+        } else {
+            SwitchState::Off
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Default, Clone, Copy, Serialize)]
 pub struct TotalPower {
     pub input: u16,
     pub output: u16,
 }
 
-#[derive(Debug, PartialEq, Eq, Default, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Default, Clone, Copy, Serialize)]
 pub struct DcPower {
     pub type_c_one_power: u16,
     pub type_c_two_power: u16,
@@ -64,7 +175,7 @@ pub struct DcPower {
     pub total: u16,
 }
 
-#[derive(Debug, PartialEq, Eq, Default, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Default, Clone, Copy, Serialize)]
 pub struct Status {
     pub low_noise: bool,
     pub low_battery_warning: bool,
@@ -80,11 +191,54 @@ pub struct Status {
     pub standby: bool,
 }
 
-#[derive(Debug, PartialEq, Eq, Default, Clone, Copy)]
+impl Status {
+    /// Current value of the setting as it is encoded in the status frame
+    pub fn setting(&self, setting: Setting) -> u8 {
+        match setting {
+            Setting::LowNoise => self.low_noise.into(),
+            Setting::UsbSwitch => self.usb_switch.into(),
+            Setting::DcSwitch => self.dc_switch.into(),
+            Setting::AcFrequency => self.ac_frequency_hz,
+            Setting::AcTurbo => self.ac_turbo.into(),
+            Setting::AcSwitch => self.ac_switch.into(),
+            Setting::WarningVoice => self.warning_voice.into(),
+            Setting::Locking => self.locking.into(),
+            Setting::KeyVoice => (!self.key_voice).into(),
+            Setting::Standby => self.standby.into(),
+        }
+    }
+}
+
+/// Response to the version request.
+///
+/// The payload is assumed to consist of NUL-separated ASCII fields,
+/// non-text fields are shown as hex. This layout has not been checked against a capture yet,
+/// so the whole payload is kept as well.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize)]
+pub struct VersionInfo {
+    pub firmware: String,
+    pub hardware: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial_number: Option<String>,
+    /// Payload of the response as hex
+    pub raw: String,
+}
+
+pub const CELL_COUNT: usize = 7;
+
+#[derive(Debug, PartialEq, Eq, Default, Clone, Copy, Serialize)]
 pub struct CapacityInfo {
     pub charge_time: u16,
     pub discharge_time: u16,
     pub battery_capacity_power: u8,
+    /// In 0.1 V units, roughly the sum of the cell voltages
+    pub pack_voltage: u16,
+    /// In mV
+    pub cell_voltages: [u16; CELL_COUNT],
+    /// In 0.01 °C units
+    pub temperature: u16,
+    /// In mAh
+    pub remaining_capacity: u16,
 }
 
 pub async fn build_device_info(
@@ -109,6 +263,7 @@ pub async fn build_device_info(
             StatsUpdate::Status(v) => status = Some(v),
             StatsUpdate::ElectricQuantityPower(v) => electric_quantity_power = Some(v),
             StatsUpdate::Capacity(v) => capacity = Some(v),
+            StatsUpdate::Key17(_) | StatsUpdate::Version(_) | StatsUpdate::Unknown { .. } => (),
         }
 
         if let (
@@ -145,3 +300,30 @@ pub async fn build_device_info(
 
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Setting, SettingValue, Status};
+
+    #[test]
+    fn key_voice_is_inverted() {
+        let status = Status {
+            key_voice: true,
+            ..Default::default()
+        };
+        let value = status.setting(Setting::KeyVoice);
+
+        assert_eq!(0, value);
+        assert_eq!(
+            SettingValue::Switch(true),
+            Setting::KeyVoice.typed_value(value)
+        );
+        assert_eq!("on", Setting::KeyVoice.format_value(value));
+    }
+
+    #[test]
+    fn ac_frequency_60_hz_is_unknown() {
+        assert_eq!("50 Hz", Setting::AcFrequency.format_value(0));
+        assert_eq!("unknown (1)", Setting::AcFrequency.format_value(1));
+    }
+}
@@ -0,0 +1,55 @@
+use std::fmt;
+use strum::IntoStaticStr;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum ParseError {
+    /// The payload is shorter than required for the given key
+    TruncatedFrame {
+        key: u8,
+        expected: usize,
+        actual: usize,
+    },
+    /// The frame header declares an implausible payload length
+    BadLength(usize),
+    ChecksumMismatch {
+        expected: u16,
+        actual: u16,
+    },
+    InvalidEnumValue {
+        key: u8,
+        value: u8,
+    },
+}
+
+impl ParseError {
+    /// Short identifier of the error type, used as a metric label
+    pub fn kind(&self) -> &'static str {
+        self.into()
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::TruncatedFrame {
+                key,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "frame with key {key:#04x} is truncated, expected at least {expected} bytes but got {actual}"
+            ),
+            ParseError::BadLength(len) => write!(f, "invalid frame length {len}"),
+            ParseError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "checksum mismatch, expected {expected:#06x} but calculated {actual:#06x}"
+            ),
+            ParseError::InvalidEnumValue { key, value } => {
+                write!(f, "invalid value {value:#04x} for key {key:#04x}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
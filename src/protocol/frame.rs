@@ -0,0 +1,326 @@
+use super::{error::ParseError, REQUEST_SEPARATOR_SEQUENCE, SEPARATOR_SEQUENCE};
+use crc16::{State, MODBUS};
+
+/// Separator, key and 2 length bytes
+const HEADER_LEN: usize = 7;
+const CHECKSUM_LEN: usize = 2;
+/// Anything longer than this is treated as a corrupted header.
+///
+/// The largest known payload is the 28 byte capacity frame (0x09), but there is no capture
+/// of the version reply (0x06) yet, so the limit leaves plenty of room for it.
+const MAX_PAYLOAD_LEN: usize = 255;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Frame {
+    pub key: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Encodes the frame the way the device sends it
+    pub fn encode(&self) -> Vec<u8> {
+        self.encode_with_separator(SEPARATOR_SEQUENCE)
+    }
+
+    /// Encodes the frame the way requests are written to the device
+    pub fn encode_request(&self) -> Vec<u8> {
+        self.encode_with_separator(REQUEST_SEPARATOR_SEQUENCE)
+    }
+
+    fn encode_with_separator(&self, separator: [u8; 4]) -> Vec<u8> {
+        let payload_len = u16::try_from(self.payload.len()).expect("Payload too long");
+
+        let mut output = Vec::with_capacity(HEADER_LEN + self.payload.len() + CHECKSUM_LEN);
+        output.extend_from_slice(&separator);
+        output.push(self.key);
+        output.extend_from_slice(&payload_len.to_le_bytes());
+        output.extend_from_slice(&self.payload);
+
+        // The checksum covers everything except the first 2 bytes of the separator
+        let crc = State::<MODBUS>::calculate(&output[2..]);
+        output.extend_from_slice(&crc.to_le_bytes());
+        output
+    }
+}
+
+/// A frame as it was received, kept even if the checksum does not match
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RawFrame {
+    pub frame: Frame,
+    pub checksum: u16,
+    pub calculated_checksum: u16,
+}
+
+impl RawFrame {
+    pub fn checksum_valid(&self) -> bool {
+        self.checksum == self.calculated_checksum
+    }
+
+    fn into_frame(self) -> Result<Frame, ParseError> {
+        if self.checksum_valid() {
+            Ok(self.frame)
+        } else {
+            Err(ParseError::ChecksumMismatch {
+                expected: self.checksum,
+                actual: self.calculated_checksum,
+            })
+        }
+    }
+}
+
+/// Reassembles frames from a sequence of notifications.
+///
+/// Frames can be split across notification boundaries, so incomplete data is kept
+/// until the rest of the frame arrives. Bytes outside of frames (such as the zero padding at the end of notifications)
+/// are discarded, and a frame with an invalid header or checksum is reported and skipped by searching for the next separator.
+#[derive(Debug)]
+pub struct FrameDecoder {
+    separator: [u8; 4],
+    buf: Vec<u8>,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self {
+            separator: SEPARATOR_SEQUENCE,
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl FrameDecoder {
+    /// Decoder for frames written to the device instead of the ones it sends
+    pub fn for_requests() -> Self {
+        Self {
+            separator: REQUEST_SEPARATOR_SEQUENCE,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Adds the notification data to the buffer and returns all frames that are now complete
+    pub fn decode(&mut self, data: &[u8]) -> Vec<Result<Frame, ParseError>> {
+        self.buf.extend_from_slice(data);
+
+        let mut frames = Vec::with_capacity(2);
+        while let Some(frame) = self.next_frame() {
+            frames.push(frame.and_then(RawFrame::into_frame));
+        }
+        frames
+    }
+
+    /// Same as [`FrameDecoder::decode`], but also returns frames with a checksum mismatch
+    pub fn decode_raw(&mut self, data: &[u8]) -> Vec<Result<RawFrame, ParseError>> {
+        self.buf.extend_from_slice(data);
+
+        let mut frames = Vec::with_capacity(2);
+        while let Some(frame) = self.next_frame() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    fn next_frame(&mut self) -> Option<Result<RawFrame, ParseError>> {
+        match find_separator(&self.buf, self.separator) {
+            Some(start) => {
+                self.buf.drain(..start);
+            }
+            None => {
+                // The end of the buffer might be the beginning of a separator
+                let keep = self.buf.len().min(self.separator.len() - 1);
+                self.buf.drain(..self.buf.len() - keep);
+                return None;
+            }
+        }
+
+        if self.buf.len() < HEADER_LEN {
+            return None;
+        }
+
+        let key = self.buf[4];
+        let payload_len = u16::from_le_bytes([self.buf[5], self.buf[6]]) as usize;
+        if payload_len > MAX_PAYLOAD_LEN {
+            self.skip_separator();
+            return Some(Err(ParseError::BadLength(payload_len)));
+        }
+
+        let frame_len = HEADER_LEN + payload_len + CHECKSUM_LEN;
+        if self.buf.len() < frame_len {
+            return None;
+        }
+
+        let to_validate = &self.buf[2..HEADER_LEN + payload_len];
+        let checksum = u16::from_le_bytes([self.buf[frame_len - 2], self.buf[frame_len - 1]]);
+        let calculated_checksum = State::<MODBUS>::calculate(to_validate);
+
+        let payload = self.buf[HEADER_LEN..HEADER_LEN + payload_len].to_vec();
+        let frame = RawFrame {
+            frame: Frame { key, payload },
+            checksum,
+            calculated_checksum,
+        };
+
+        if frame.checksum_valid() {
+            self.buf.drain(..frame_len);
+        } else {
+            self.skip_separator();
+        }
+        Some(Ok(frame))
+    }
+
+    fn skip_separator(&mut self) {
+        self.buf.drain(..self.separator.len());
+    }
+}
+
+fn find_separator(data: &[u8], separator: [u8; 4]) -> Option<usize> {
+    data.windows(separator.len())
+        .position(|window| window == separator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Frame, FrameDecoder, RawFrame};
+    use crate::protocol::error::ParseError;
+    use pretty_assertions::assert_eq;
+
+    const TOTAL_POWER_FRAME: [u8; 13] = [
+        0x5a, 0xa5, 0xc0, 0xa1, 0x0f, 0x04, 0x00, 0x00, 0x00, 0x03, 0x00, 0x6f, 0x35,
+    ];
+    const FLASHLIGHT_FRAME: [u8; 10] = [0x5a, 0xa5, 0xc0, 0xa1, 0x13, 0x01, 0x00, 0x01, 0x38, 0x46];
+
+    fn total_power() -> Frame {
+        Frame {
+            key: 0x0f,
+            payload: vec![0x00, 0x00, 0x03, 0x00],
+        }
+    }
+
+    fn flashlight() -> Frame {
+        Frame {
+            key: 0x13,
+            payload: vec![0x01],
+        }
+    }
+
+    #[test]
+    fn whole_frames() {
+        let mut data = TOTAL_POWER_FRAME.to_vec();
+        data.extend_from_slice(&FLASHLIGHT_FRAME);
+        data.extend_from_slice(&[0x00; 16]);
+
+        let mut decoder = FrameDecoder::default();
+        assert_eq!(
+            vec![Ok(total_power()), Ok(flashlight())],
+            decoder.decode(&data)
+        );
+    }
+
+    #[test]
+    fn frame_split_across_notifications() {
+        let mut data = TOTAL_POWER_FRAME.to_vec();
+        data.extend_from_slice(&FLASHLIGHT_FRAME);
+        let (first, second) = data.split_at(17);
+
+        let mut decoder = FrameDecoder::default();
+        assert_eq!(vec![Ok(total_power())], decoder.decode(first));
+        assert_eq!(vec![Ok(flashlight())], decoder.decode(second));
+    }
+
+    #[test]
+    fn byte_by_byte() {
+        let mut decoder = FrameDecoder::default();
+        let frames = TOTAL_POWER_FRAME
+            .iter()
+            .chain(&FLASHLIGHT_FRAME)
+            .flat_map(|byte| decoder.decode(&[*byte]))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![Ok(total_power()), Ok(flashlight())], frames);
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!(TOTAL_POWER_FRAME.to_vec(), total_power().encode());
+    }
+
+    #[test]
+    fn resync_after_garbage() {
+        let mut data = vec![0x12, 0x34, 0x5a, 0xa5];
+        data.extend_from_slice(&TOTAL_POWER_FRAME);
+
+        let mut decoder = FrameDecoder::default();
+        assert_eq!(vec![Ok(total_power())], decoder.decode(&data));
+    }
+
+    #[test]
+    fn resync_after_truncated_frame() {
+        // The rest of the first frame was lost, so it swallows the beginning of the next one
+        let mut data = TOTAL_POWER_FRAME[..9].to_vec();
+        data.extend_from_slice(&FLASHLIGHT_FRAME);
+
+        let mut decoder = FrameDecoder::default();
+        assert_eq!(
+            vec![
+                Err(ParseError::ChecksumMismatch {
+                    expected: 0xa1c0,
+                    actual: 0x1e95,
+                }),
+                Ok(flashlight())
+            ],
+            decoder.decode(&data)
+        );
+    }
+
+    #[test]
+    fn bad_length() {
+        let mut data = vec![0x5a, 0xa5, 0xc0, 0xa1, 0x0f, 0xff, 0xff];
+        data.extend_from_slice(&FLASHLIGHT_FRAME);
+
+        let mut decoder = FrameDecoder::default();
+        assert_eq!(
+            vec![Err(ParseError::BadLength(0xffff)), Ok(flashlight())],
+            decoder.decode(&data)
+        );
+    }
+
+    #[test]
+    fn resync_after_corrupted_length() {
+        // The length is plausible, but the frames that follow are read as its payload
+        let mut data = vec![0x5a, 0xa5, 0xc0, 0xa1, 0x0f, 0x20, 0x00];
+        for _ in 0..4 {
+            data.extend_from_slice(&FLASHLIGHT_FRAME);
+        }
+
+        let mut decoder = FrameDecoder::default();
+        let frames = decoder.decode(&data);
+        assert!(matches!(
+            frames[0],
+            Err(ParseError::ChecksumMismatch { .. })
+        ));
+        assert_eq!(vec![Ok(flashlight()); 4], frames[1..]);
+    }
+
+    #[test]
+    fn raw_checksum_mismatch() {
+        let mut data = TOTAL_POWER_FRAME.to_vec();
+        data[11] = 0x00;
+        data.extend_from_slice(&FLASHLIGHT_FRAME);
+
+        let mut decoder = FrameDecoder::default();
+        let frames = decoder.decode_raw(&data);
+        assert_eq!(
+            vec![
+                Ok(RawFrame {
+                    frame: total_power(),
+                    checksum: 0x3500,
+                    calculated_checksum: 0x356f,
+                }),
+                Ok(RawFrame {
+                    frame: flashlight(),
+                    checksum: 0x4638,
+                    calculated_checksum: 0x4638,
+                }),
+            ],
+            frames
+        );
+    }
+}
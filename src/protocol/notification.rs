@@ -1,11 +1,15 @@
-use crc16::{State, MODBUS};
-
 use super::{
-    device_info::{CapacityInfo, DcPower, FlashlightMode, Power, Status, TotalPower},
-    SEPARATOR_SEQUENCE,
+    device_info::{
+        CapacityInfo, DcPower, FlashlightMode, Power, Status, TotalPower, VersionInfo, CELL_COUNT,
+    },
+    error::ParseError,
+    frame::{Frame, FrameDecoder},
 };
+use crate::hex;
+use strum::IntoStaticStr;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
 pub enum StatsUpdate {
     Power(Power),
     TotalPower(TotalPower),
@@ -15,115 +19,266 @@ pub enum StatsUpdate {
     Status(Status),
     ElectricQuantityPower(u8),
     Capacity(CapacityInfo),
+    /// Sent along with every status, the bytes were zero in all captures so far,
+    /// so they are kept raw until their meaning is known
+    Key17([u8; 6]),
+    Version(VersionInfo),
+    Unknown {
+        key: u8,
+        payload: Vec<u8>,
+    },
 }
 
-pub fn process_notification(data: &[u8]) -> Vec<StatsUpdate> {
-    let mut updates = Vec::with_capacity(2);
-
-    let mut start = 0;
-    for i in 0..data.len() - 4 {
-        if data[i..i + 4] == SEPARATOR_SEQUENCE {
-            let value = &data[start..i];
-            updates.extend(process_value(value));
-            start = i;
-        }
+impl StatsUpdate {
+    /// Short identifier of the update type, used as a metric label
+    pub fn kind(&self) -> &'static str {
+        self.into()
     }
-    updates.extend(process_value(&data[start..]));
-    updates
-}
 
-fn process_value(value: &[u8]) -> Option<StatsUpdate> {
-    if value.is_empty() {
-        return None;
+    /// Encodes the update into a frame the way the device sends it
+    pub fn to_frame(&self) -> Frame {
+        let (key, payload) = match self {
+            StatsUpdate::Power(power) => (
+                0x04,
+                [
+                    power.batteries_one_power,
+                    power.batteries_two_power,
+                    power.inverter_one_power,
+                    power.inverter_two_power,
+                ]
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+            ),
+            StatsUpdate::Version(version) => {
+                let mut payload = version.firmware.as_bytes().to_vec();
+                payload.push(0);
+                payload.extend_from_slice(version.hardware.as_bytes());
+                if let Some(serial_number) = &version.serial_number {
+                    payload.push(0);
+                    payload.extend_from_slice(serial_number.as_bytes());
+                }
+                (0x06, payload)
+            }
+            StatsUpdate::Capacity(capacity) => {
+                let mut payload = vec![0; 28];
+                payload[0..2].copy_from_slice(&capacity.pack_voltage.to_le_bytes());
+                for (buf, cell) in payload[2..].chunks_exact_mut(2).zip(capacity.cell_voltages) {
+                    buf.copy_from_slice(&cell.to_le_bytes());
+                }
+                payload[16..18].copy_from_slice(&capacity.temperature.to_le_bytes());
+                payload[19..21].copy_from_slice(&capacity.charge_time.to_le_bytes());
+                payload[21..23].copy_from_slice(&capacity.discharge_time.to_le_bytes());
+                payload[23] = capacity.battery_capacity_power;
+                payload[24..26].copy_from_slice(&capacity.remaining_capacity.to_le_bytes());
+                (0x09, payload)
+            }
+            StatsUpdate::AcPower(value) => {
+                let mut payload = vec![0; 8];
+                payload[6..8].copy_from_slice(&value.to_le_bytes());
+                (0x0b, payload)
+            }
+            StatsUpdate::DcPower(power) => {
+                let mut payload: Vec<u8> = [
+                    power.type_c_one_power,
+                    power.type_c_two_power,
+                    power.usb_one_power,
+                    power.usb_two_power,
+                    power.total,
+                ]
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect();
+                payload.resize(14, 0);
+                (0x0c, payload)
+            }
+            StatsUpdate::TotalPower(total) => (
+                0x0f,
+                [total.input, total.output]
+                    .iter()
+                    .flat_map(|value| value.to_le_bytes())
+                    .collect(),
+            ),
+            StatsUpdate::FlashlightStatus(mode) => (0x13, vec![*mode as u8]),
+            StatsUpdate::ElectricQuantityPower(value) => (0x15, vec![*value]),
+            StatsUpdate::Status(status) => (
+                0x16,
+                vec![
+                    status.low_noise.into(),
+                    status.low_battery_warning.into(),
+                    status.usb_switch.into(),
+                    status.dc_switch.into(),
+                    status.ac_frequency_hz,
+                    status.warning_voice.into(),
+                    status.ac_turbo.into(),
+                    status.ac_switch.into(),
+                    status.battery_health.into(),
+                    status.locking.into(),
+                    (!status.key_voice).into(),
+                    status.standby.into(),
+                ],
+            ),
+            StatsUpdate::Key17(bytes) => (0x17, bytes.to_vec()),
+            StatsUpdate::Unknown { key, payload } => (*key, payload.clone()),
+        };
+        Frame { key, payload }
     }
+}
 
-    let key = value[4];
-    let end_offset = parse_u16(&value[5..7]) as usize;
+pub fn process_notification(
+    decoder: &mut FrameDecoder,
+    data: &[u8],
+) -> Vec<Result<StatsUpdate, ParseError>> {
+    decoder
+        .decode(data)
+        .into_iter()
+        .map(|frame| frame.and_then(|frame| process_frame(&frame)))
+        .collect()
+}
 
-    let payload = &value[7..end_offset + 9];
-    let to_validate = &value[2..end_offset + 7];
-    let (value, checksum_buf) = payload.split_at(payload.len() - 2);
+pub fn process_frame(frame: &Frame) -> Result<StatsUpdate, ParseError> {
+    match frame.key {
+        0x04 => {
+            let value = payload_with_len(frame, 8)?;
+            Ok(StatsUpdate::Power(Power {
+                batteries_one_power: parse_u16(&value[0..2]),
+                batteries_two_power: parse_u16(&value[2..4]),
+                inverter_one_power: parse_u16(&value[4..6]),
+                inverter_two_power: parse_u16(&value[6..8]),
+            }))
+        }
+        0x06 => {
+            let mut fields = frame
+                .payload
+                .split(|byte| *byte == 0)
+                .filter(|field| !field.is_empty())
+                .map(parse_text_field);
+            Ok(StatsUpdate::Version(VersionInfo {
+                firmware: fields.next().unwrap_or_default(),
+                hardware: fields.next().unwrap_or_default(),
+                serial_number: fields.next(),
+                raw: hex::encode(&frame.payload),
+            }))
+        }
+        0x09 => {
+            let value = payload_with_len(frame, 26)?;
+            let mut cell_voltages = [0; CELL_COUNT];
+            for (cell, buf) in cell_voltages.iter_mut().zip(value[2..].chunks_exact(2)) {
+                *cell = parse_u16(buf);
+            }
 
-    let expected_checksum = parse_u16(checksum_buf);
-    let calculated_checksum = State::<MODBUS>::calculate(to_validate);
+            Ok(StatsUpdate::Capacity(CapacityInfo {
+                pack_voltage: parse_u16(&value[0..2]),
+                cell_voltages,
+                temperature: parse_u16(&value[16..18]),
+                charge_time: parse_u16(&value[19..21]),
+                discharge_time: parse_u16(&value[21..23]),
+                battery_capacity_power: value[23],
+                remaining_capacity: parse_u16(&value[24..26]),
+            }))
+        }
+        0x0b => {
+            let value = payload_with_len(frame, 8)?;
+            Ok(StatsUpdate::AcPower(parse_u16(&value[6..8])))
+        }
+        0x0c => {
+            let value = payload_with_len(frame, 10)?;
+            Ok(StatsUpdate::DcPower(DcPower {
+                type_c_one_power: parse_u16(&value[0..2]),
+                type_c_two_power: parse_u16(&value[2..4]),
+                usb_one_power: parse_u16(&value[4..6]),
+                usb_two_power: parse_u16(&value[6..8]),
+                total: parse_u16(&value[8..10]),
+            }))
+        }
+        0x0f => {
+            let value = payload_with_len(frame, 4)?;
+            Ok(StatsUpdate::TotalPower(TotalPower {
+                input: parse_u16(&value[0..2]),
+                output: parse_u16(&value[2..4]),
+            }))
+        }
+        0x13 => {
+            let value = payload_with_len(frame, 1)?;
+            let mode = FlashlightMode::from_repr(value[0] as usize).ok_or(
+                ParseError::InvalidEnumValue {
+                    key: frame.key,
+                    value: value[0],
+                },
+            )?;
+            Ok(StatsUpdate::FlashlightStatus(mode))
+        }
+        0x15 => {
+            let value = payload_with_len(frame, 1)?;
+            Ok(StatsUpdate::ElectricQuantityPower(value[0]))
+        }
+        0x16 => {
+            let value = payload_with_len(frame, 12)?;
+            Ok(StatsUpdate::Status(Status {
+                low_noise: value[0] != 0,
+                low_battery_warning: value[1] != 0,
+                usb_switch: value[2] != 0,
+                dc_switch: value[3] != 0,
+                ac_frequency_hz: value[4],
+                warning_voice: value[5] != 0,
+                ac_turbo: value[6] != 0,
+                ac_switch: value[7] != 0,
+                battery_health: value[8] != 0,
+                locking: value[9] != 0,
+                key_voice: value[10] == 0,
+                standby: value[11] != 0,
+            }))
+        }
+        0x17 => {
+            let value = payload_with_len(frame, 6)?;
+            let mut bytes = [0; 6];
+            bytes.copy_from_slice(&value[..6]);
+            Ok(StatsUpdate::Key17(bytes))
+        }
+        key => Ok(StatsUpdate::Unknown {
+            key,
+            payload: frame.payload.clone(),
+        }),
+    }
+}
 
-    if calculated_checksum != expected_checksum {
-        eprintln!(
-            "Checksum validation not passed, payload {:?}",
-            value
-                .iter()
-                .map(|item| format!("{item:x}"))
-                .collect::<Vec<_>>()
-                .join("")
-        );
-        return None;
+/// Returns the frame payload if it has at least `len` bytes
+fn payload_with_len(frame: &Frame, len: usize) -> Result<&[u8], ParseError> {
+    if frame.payload.len() < len {
+        return Err(ParseError::TruncatedFrame {
+            key: frame.key,
+            expected: len,
+            actual: frame.payload.len(),
+        });
     }
+    Ok(&frame.payload)
+}
 
-    match key {
-        0x04 if value.len() >= 8 => Some(StatsUpdate::Power(Power {
-            batteries_one_power: parse_u16(&value[0..2]),
-            batteries_two_power: parse_u16(&value[2..4]),
-            inverter_one_power: parse_u16(&value[4..6]),
-            inverter_two_power: parse_u16(&value[6..8]),
-        })),
-        0x09 if value.len() >= 24 => Some(StatsUpdate::Capacity(CapacityInfo {
-            charge_time: parse_u16(&value[19..21]),
-            discharge_time: parse_u16(&value[21..23]),
-            battery_capacity_power: value[23],
-        })),
-        0x0b if value.len() >= 8 => {
-            let buf = &value[6..8];
-            let ac_power = u16::from_le_bytes(buf.try_into().unwrap());
-            Some(StatsUpdate::AcPower(ac_power))
-        }
-        0x0c if value.len() >= 10 => Some(StatsUpdate::DcPower(DcPower {
-            type_c_one_power: parse_u16(&value[0..2]),
-            type_c_two_power: parse_u16(&value[2..4]),
-            usb_one_power: parse_u16(&value[4..6]),
-            usb_two_power: parse_u16(&value[6..8]),
-            total: parse_u16(&value[8..10]),
-        })),
-        0x0f if value.len() >= 4 => Some(StatsUpdate::TotalPower(TotalPower {
-            input: parse_u16(&value[0..2]),
-            output: parse_u16(&value[2..4]),
-        })),
-        0x13 if !value.is_empty() => {
-            let mode = FlashlightMode::from_repr(value[0] as usize).unwrap();
-            Some(StatsUpdate::FlashlightStatus(mode))
-        }
-        0x15 if !value.is_empty() => Some(StatsUpdate::ElectricQuantityPower(value[0])),
-        0x16 if value.len() >= 12 => Some(StatsUpdate::Status(Status {
-            low_noise: value[0] != 0,
-            low_battery_warning: value[1] != 0,
-            usb_switch: value[2] != 0,
-            dc_switch: value[3] != 0,
-            ac_frequency_hz: value[4],
-            warning_voice: value[5] != 0,
-            ac_turbo: value[6] != 0,
-            ac_switch: value[7] != 0,
-            battery_health: value[8] != 0,
-            locking: value[9] != 0,
-            key_voice: value[10] == 0,
-            standby: value[11] != 0,
-        })),
-        _ => None,
+fn parse_text_field(data: &[u8]) -> String {
+    match std::str::from_utf8(data) {
+        Ok(text) if text.chars().all(|c| c.is_ascii_graphic() || c == ' ') => text.to_owned(),
+        _ => hex::encode(data),
     }
 }
 
 fn parse_u16(data: &[u8]) -> u16 {
-    u16::from_le_bytes(data.try_into().unwrap())
+    u16::from_le_bytes([data[0], data[1]])
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{process_notification, StatsUpdate};
-    use crate::protocol::device_info::{
-        CapacityInfo, DcPower, FlashlightMode, Power, Status, TotalPower,
+    use super::{process_frame, process_notification, StatsUpdate};
+    use crate::protocol::{
+        device_info::{
+            CapacityInfo, DcPower, FlashlightMode, Power, Status, TotalPower, VersionInfo,
+        },
+        error::ParseError,
+        frame::{Frame, FrameDecoder},
     };
     use pretty_assertions::assert_eq;
 
-    fn assert_stats(data: &[u8], expected_updates: &[StatsUpdate]) {
-        let updates = process_notification(data);
+    fn assert_stats(data: &[u8], expected_updates: &[Result<StatsUpdate, ParseError>]) {
+        let updates = process_notification(&mut FrameDecoder::default(), data);
         assert_eq!(expected_updates, updates);
     }
 
@@ -144,11 +299,11 @@ mod tests {
         assert_stats(
             &data,
             &[
-                StatsUpdate::TotalPower(TotalPower {
+                Ok(StatsUpdate::TotalPower(TotalPower {
                     input: 0,
                     output: 3,
-                }),
-                StatsUpdate::AcPower(0),
+                })),
+                Ok(StatsUpdate::AcPower(0)),
             ],
         );
     }
@@ -170,7 +325,7 @@ mod tests {
         assert_stats(
             &data,
             &[
-                StatsUpdate::Status(Status {
+                Ok(StatsUpdate::Status(Status {
                     low_noise: true,
                     low_battery_warning: false,
                     usb_switch: true,
@@ -183,8 +338,9 @@ mod tests {
                     locking: false,
                     key_voice: true,
                     standby: false,
-                }),
-                StatsUpdate::FlashlightStatus(FlashlightMode::Low),
+                })),
+                Ok(StatsUpdate::FlashlightStatus(FlashlightMode::Low)),
+                Ok(StatsUpdate::Key17([0; 6])),
             ],
         );
     }
@@ -206,7 +362,7 @@ mod tests {
         assert_stats(
             &data,
             &[
-                StatsUpdate::Status(Status {
+                Ok(StatsUpdate::Status(Status {
                     low_noise: true,
                     low_battery_warning: false,
                     usb_switch: true,
@@ -219,8 +375,9 @@ mod tests {
                     locking: false,
                     key_voice: true,
                     standby: false,
-                }),
-                StatsUpdate::FlashlightStatus(FlashlightMode::Off),
+                })),
+                Ok(StatsUpdate::FlashlightStatus(FlashlightMode::Off)),
+                Ok(StatsUpdate::Key17([0; 6])),
             ],
         );
     }
@@ -242,14 +399,14 @@ mod tests {
         assert_stats(
             &data,
             &[
-                StatsUpdate::DcPower(DcPower {
+                Ok(StatsUpdate::DcPower(DcPower {
                     type_c_one_power: 0,
                     type_c_two_power: 0,
                     usb_one_power: 0,
                     usb_two_power: 3,
                     total: 0,
-                }),
-                StatsUpdate::ElectricQuantityPower(0),
+                })),
+                Ok(StatsUpdate::ElectricQuantityPower(0)),
             ],
         );
     }
@@ -271,18 +428,109 @@ mod tests {
         assert_stats(
             &data,
             &[
-                StatsUpdate::Power(Power {
+                Ok(StatsUpdate::Power(Power {
                     batteries_one_power: 68,
                     batteries_two_power: 71,
                     inverter_one_power: 85,
                     inverter_two_power: 77,
-                }),
-                StatsUpdate::Capacity(CapacityInfo {
+                })),
+                Ok(StatsUpdate::Capacity(CapacityInfo {
                     charge_time: 0,
                     discharge_time: 5588,
                     battery_capacity_power: 90,
-                }),
+                    pack_voltage: 242,
+                    cell_voltages: [3454, 3475, 3475, 3470, 3472, 3474, 3474],
+                    temperature: 2404,
+                    remaining_capacity: 27300,
+                })),
             ],
         );
     }
+
+    #[test]
+    fn truncated_payload() {
+        let frame = Frame {
+            key: 0x0f,
+            payload: vec![0x00, 0x00, 0x03],
+        };
+        assert_eq!(
+            Err(ParseError::TruncatedFrame {
+                key: 0x0f,
+                expected: 4,
+                actual: 3,
+            }),
+            process_frame(&frame)
+        );
+    }
+
+    #[test]
+    fn invalid_flashlight_mode() {
+        let frame = Frame {
+            key: 0x13,
+            payload: vec![0x07],
+        };
+        assert_eq!(
+            Err(ParseError::InvalidEnumValue {
+                key: 0x13,
+                value: 0x07,
+            }),
+            process_frame(&frame)
+        );
+    }
+
+    #[test]
+    fn version() {
+        // Follows the assumed layout, there is no capture of a version response yet
+        let frame = Frame {
+            key: 0x06,
+            payload: b"V1.0.12\0HW2.1\0\x01\x02".to_vec(),
+        };
+        assert_eq!(
+            Ok(StatsUpdate::Version(VersionInfo {
+                firmware: "V1.0.12".to_owned(),
+                hardware: "HW2.1".to_owned(),
+                serial_number: Some("0102".to_owned()),
+                raw: "56312e302e3132004857322e31000102".to_owned(),
+            })),
+            process_frame(&frame)
+        );
+    }
+
+    #[test]
+    fn unknown_key() {
+        let frame = Frame {
+            key: 0x20,
+            payload: vec![0x01, 0x02],
+        };
+        assert_eq!(
+            Ok(StatsUpdate::Unknown {
+                key: 0x20,
+                payload: vec![0x01, 0x02],
+            }),
+            process_frame(&frame)
+        );
+    }
+
+    #[test]
+    fn short_notification() {
+        assert_stats(&[0x5a, 0xa5], &[]);
+        assert_stats(&[], &[]);
+    }
+
+    #[test]
+    fn to_frame_round_trip() {
+        let data = [
+            0x5a, 0xa5, 0xc0, 0xa1, 0x04, 0x08, 0x00, 0x44, 0x00, 0x47, 0x00, 0x55, 0x00, 0x4d,
+            0x00, 0xc6, 0x1a, 0x5a, 0xa5, 0xc0, 0xa1, 0x16, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x01,
+            0x00, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x9e, 0x0c, 0x5a, 0xa5, 0xc0, 0xa1,
+            0x0c, 0x0e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x6a, 0x99,
+        ];
+
+        let encoded: Vec<u8> = process_notification(&mut FrameDecoder::default(), &data)
+            .into_iter()
+            .flat_map(|update| update.unwrap().to_frame().encode())
+            .collect();
+        assert_eq!(data.to_vec(), encoded);
+    }
 }
@@ -1,44 +1,68 @@
-use crc16::{State, MODBUS};
+use super::{device_info::FlashlightMode, frame::Frame, notification::StatsUpdate};
 
-pub fn request_to_buf(value: &[u8]) -> Vec<u8> {
-    let value_trimmed = trim_slice_start(value);
-    let value_size = value_trimmed.len();
-
-    let mut base_value = vec![0; 2 + value_size];
-    base_value[0..2].copy_from_slice(&0xA1C0u16.to_be_bytes());
-    base_value[2..].copy_from_slice(value_trimmed);
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Request {
+    Version,
+    Flashlight(FlashlightMode),
+}
 
-    let crc = State::<MODBUS>::calculate(&base_value);
+impl Request {
+    /// Parses a request frame, returns `None` for requests that are not known
+    pub fn from_frame(frame: &Frame) -> Option<Self> {
+        match (frame.key, frame.payload.as_slice()) {
+            (0x06, _) => Some(Request::Version),
+            (0x13, [mode, ..]) => {
+                FlashlightMode::from_repr(*mode as usize).map(Request::Flashlight)
+            }
+            _ => None,
+        }
+    }
 
-    let mut output = vec![0; 6 + value_size];
+    pub fn key(&self) -> u8 {
+        match self {
+            Request::Version => 0x06,
+            Request::Flashlight(_) => 0x13,
+        }
+    }
 
-    output[0..4].copy_from_slice(&0x5AA5A1C0u32.to_be_bytes());
-    output[4..4 + value_size].copy_from_slice(value_trimmed);
-    output[4 + value_size..].copy_from_slice(&crc.to_le_bytes());
+    pub fn payload(&self) -> Vec<u8> {
+        match self {
+            Request::Version => vec![],
+            Request::Flashlight(mode) => vec![*mode as u8],
+        }
+    }
 
-    output
-}
+    /// Checks if the update shows that the device has processed the request
+    pub fn is_confirmed_by(&self, update: &StatsUpdate) -> bool {
+        match (self, update) {
+            (Request::Version, StatsUpdate::Version(_)) => true,
+            (Request::Flashlight(mode), StatsUpdate::FlashlightStatus(current)) => mode == current,
+            _ => false,
+        }
+    }
 
-fn trim_slice_start(value: &[u8]) -> &[u8] {
-    let mut result = value;
-    while let [first, rest @ ..] = result {
-        if *first == 0 {
-            result = rest;
-        } else {
-            break;
+    /// Encodes the request into a frame that can be written to the device
+    pub fn encode(&self) -> Vec<u8> {
+        Frame {
+            key: self.key(),
+            payload: self.payload(),
         }
+        .encode_request()
     }
-    result
 }
 
 #[cfg(test)]
 mod tests {
-    use super::request_to_buf;
+    use super::Request;
+    use crate::protocol::{
+        device_info::FlashlightMode,
+        frame::{Frame, FrameDecoder},
+        notification::StatsUpdate,
+    };
 
     #[test]
     fn disable_light() {
-        let value = [0x13, 0x01, 0x00, 0x00];
-        let output = request_to_buf(&value);
+        let output = Request::Flashlight(FlashlightMode::Off).encode();
 
         let expected_data = vec![0x5a, 0xa5, 0xa1, 0xc0, 0x13, 0x01, 0x00, 0x00, 0x4c, 0x3f];
         assert_eq!(expected_data, output);
@@ -46,8 +70,7 @@ mod tests {
 
     #[test]
     fn enable_light() {
-        let value = [0x13, 0x01, 0x00, 0x01];
-        let output = request_to_buf(&value);
+        let output = Request::Flashlight(FlashlightMode::Low).encode();
 
         let expected_data = vec![0x5a, 0xa5, 0xa1, 0xc0, 0x13, 0x01, 0x00, 0x01, 0x8d, 0xff];
         assert_eq!(expected_data, output);
@@ -55,8 +78,7 @@ mod tests {
 
     #[test]
     fn enable_light_3() {
-        let value = [0x13, 0x01, 0x00, 0x03];
-        let output = request_to_buf(&value);
+        let output = Request::Flashlight(FlashlightMode::Strobe).encode();
 
         let expected_data = vec![0x5a, 0xa5, 0xa1, 0xc0, 0x13, 0x01, 0x00, 0x03, 0x0c, 0x3e];
         assert_eq!(expected_data, output);
@@ -64,8 +86,7 @@ mod tests {
 
     #[test]
     fn get_version() {
-        let value = [0x06, 0x00, 0x00];
-        let output = request_to_buf(&value);
+        let output = Request::Version.encode();
 
         let expected_data = vec![0x5a, 0xa5, 0xa1, 0xc0, 0x06, 0x00, 0x00, 0x45, 0xd8];
         assert_eq!(expected_data, output);
@@ -73,9 +94,71 @@ mod tests {
 
     #[test]
     fn idk() {
-        let value = [0x20, 0x00, 0x00];
-        let output = request_to_buf(&value);
+        let output = Frame {
+            key: 0x20,
+            payload: vec![],
+        }
+        .encode_request();
         let expected_data = vec![0x5a, 0xa5, 0xa1, 0xc0, 0x20, 0x00, 0x00, 0xa4, 0x13];
         assert_eq!(expected_data, output);
     }
+
+    #[test]
+    fn leading_zero_payload() {
+        let output = Frame {
+            key: 0x16,
+            payload: vec![0x00, 0x01],
+        }
+        .encode_request();
+        assert_eq!(&[0x16, 0x02, 0x00, 0x00, 0x01], &output[4..9]);
+    }
+
+    #[test]
+    fn flashlight_confirmation() {
+        let request = Request::Flashlight(FlashlightMode::High);
+
+        assert!(!request.is_confirmed_by(&StatsUpdate::FlashlightStatus(FlashlightMode::Low)));
+        assert!(request.is_confirmed_by(&StatsUpdate::FlashlightStatus(FlashlightMode::High)));
+        assert!(!request.is_confirmed_by(&StatsUpdate::AcPower(1)));
+    }
+
+    #[test]
+    fn decode_captured() {
+        // Bytes the vendor app wrote, the same ones as in the encoding tests above
+        let captured: [(&[u8], Request); 4] = [
+            (
+                &[0x5a, 0xa5, 0xa1, 0xc0, 0x13, 0x01, 0x00, 0x00, 0x4c, 0x3f],
+                Request::Flashlight(FlashlightMode::Off),
+            ),
+            (
+                &[0x5a, 0xa5, 0xa1, 0xc0, 0x13, 0x01, 0x00, 0x01, 0x8d, 0xff],
+                Request::Flashlight(FlashlightMode::Low),
+            ),
+            (
+                &[0x5a, 0xa5, 0xa1, 0xc0, 0x13, 0x01, 0x00, 0x03, 0x0c, 0x3e],
+                Request::Flashlight(FlashlightMode::Strobe),
+            ),
+            (
+                &[0x5a, 0xa5, 0xa1, 0xc0, 0x06, 0x00, 0x00, 0x45, 0xd8],
+                Request::Version,
+            ),
+        ];
+
+        for (bytes, request) in captured {
+            let frames = FrameDecoder::for_requests().decode(bytes);
+            let frame = frames[0].as_ref().unwrap();
+            assert_eq!(Some(request), Request::from_frame(frame));
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let requests = [Request::Version, Request::Flashlight(FlashlightMode::Sos)];
+
+        for request in requests {
+            let frames = FrameDecoder::for_requests().decode(&request.encode());
+            let frame = frames[0].as_ref().unwrap();
+            assert_eq!(Some(request), Request::from_frame(frame));
+        }
+    }
 }
@@ -0,0 +1,77 @@
+pub mod bluetooth;
+#[cfg(test)]
+pub mod mock;
+pub mod replay;
+pub mod simulator;
+
+use futures::stream::BoxStream;
+use std::fmt;
+
+// Addresses of transports that aren't backed by a real device. They are locally
+// administered, so they can't clash with the address of any real hardware.
+
+#[cfg(test)]
+pub const MOCK_ADDRESS: &str = "02:00:00:00:00:00";
+/// Address of the simulated device
+pub const SIMULATOR_ADDRESS: &str = "02:00:00:00:00:01";
+/// Address of replayed captures, which don't contain the address of the device
+pub const REPLAY_ADDRESS: &str = "02:00:00:00:00:02";
+
+/// Connection to a power station
+#[allow(async_fn_in_trait)]
+pub trait Transport {
+    /// Name the device advertises itself with
+    async fn name(&self) -> anyhow::Result<String>;
+
+    /// Address that identifies the device, even if several have the same name
+    fn address(&self) -> String;
+
+    /// Subscribes to the device notifications, yielding the raw value of each one
+    async fn subscribe(&self) -> anyhow::Result<BoxStream<'static, Vec<u8>>>;
+
+    /// Writes an encoded request to the device
+    async fn write(&self, data: &[u8]) -> anyhow::Result<()>;
+
+    /// Restores the connection after the notification stream has ended,
+    /// fails with [`NotReconnectableError`] if that will never be possible
+    async fn reconnect(&self) -> anyhow::Result<()>;
+}
+
+/// Finds the transport of a device that might not be available yet
+#[allow(async_fn_in_trait)]
+pub trait Discover {
+    type Transport: Transport;
+
+    /// What is looked for, e.g. the name or address given on the command line
+    fn filter(&self) -> String;
+
+    /// Returns the transport once the device was found
+    async fn discover(&self) -> anyhow::Result<Option<&Self::Transport>>;
+}
+
+/// Transports that don't need a lookup are always found
+impl<T: Transport> Discover for T {
+    type Transport = T;
+
+    fn filter(&self) -> String {
+        self.address()
+    }
+
+    async fn discover(&self) -> anyhow::Result<Option<&T>> {
+        Ok(Some(self))
+    }
+}
+
+/// The transport can't be reconnected, e.g. because a replayed capture has ended
+#[derive(Debug)]
+pub struct NotReconnectableError {
+    pub reason: &'static str,
+}
+
+impl fmt::Display for NotReconnectableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl std::error::Error for NotReconnectableError {}
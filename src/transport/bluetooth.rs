@@ -0,0 +1,184 @@
+use super::{Discover, Transport};
+use anyhow::Context;
+use btleplug::{
+    api::{
+        Central, CentralEvent, CharPropFlags, Characteristic, Peripheral as _, ScanFilter,
+        WriteType,
+    },
+    platform::{Adapter, Peripheral},
+};
+use futures::{stream::BoxStream, StreamExt};
+use std::{cell::OnceCell, time::Duration};
+
+/// How often to check if the device is still connected while subscribed
+const CONNECTION_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct BluetoothTransport {
+    peripheral: Peripheral,
+}
+
+impl BluetoothTransport {
+    /// Finds an already connected device with a name that contains `device_name`, or with `device_name` as its address
+    pub async fn discover(adapter: &Adapter, device_name: &str) -> anyhow::Result<Option<Self>> {
+        let peripherals = adapter.peripherals().await?;
+
+        for peripheral in peripherals {
+            if peripheral
+                .address()
+                .to_string()
+                .eq_ignore_ascii_case(device_name)
+            {
+                return Ok(Some(Self { peripheral }));
+            }
+            if let Some(properties) = peripheral.properties().await? {
+                if let Some(name) = properties.local_name {
+                    if name.contains(device_name) {
+                        return Ok(Some(Self { peripheral }));
+                    }
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Scans for a device with a name that contains `device_name` and connects to it
+    pub async fn connect(adapter: &Adapter, device_name: &str) -> anyhow::Result<Self> {
+        let mut events = adapter.events().await?;
+        adapter
+            .start_scan(ScanFilter::default())
+            .await
+            .context("Could not start scan")?;
+
+        while let Some(event) = events.next().await {
+            if let CentralEvent::DeviceDiscovered(peripheral_id) = event {
+                let peripheral = adapter.peripheral(&peripheral_id).await?;
+                let properties = peripheral.properties().await?;
+
+                if let Some(name) = properties.and_then(|props| props.local_name) {
+                    if name.contains(device_name) {
+                        println!("Found device '{name}', connecting");
+
+                        adapter.stop_scan().await?;
+                        peripheral.connect().await.context("Failed to connect")?;
+                        return Ok(Self { peripheral });
+                    }
+                }
+            }
+        }
+
+        anyhow::bail!("Adapter event stream ended before the device was found")
+    }
+
+    async fn find_characteristic(&self, flag: CharPropFlags) -> anyhow::Result<Characteristic> {
+        self.peripheral
+            .discover_services()
+            .await
+            .context("Could not discover services")?;
+
+        self.peripheral
+            .characteristics()
+            .into_iter()
+            .find(|characteristic| characteristic.properties.contains(flag))
+            .with_context(|| format!("Could not find a {flag:?} characteristic"))
+    }
+}
+
+/// Looks for a device with [`BluetoothTransport::discover`] until it is found
+pub struct BluetoothDiscovery {
+    adapter: Adapter,
+    filter: String,
+    transport: OnceCell<BluetoothTransport>,
+}
+
+impl BluetoothDiscovery {
+    pub fn new(adapter: &Adapter, filter: &str) -> Self {
+        Self {
+            adapter: adapter.clone(),
+            filter: filter.to_owned(),
+            transport: OnceCell::new(),
+        }
+    }
+}
+
+impl Discover for BluetoothDiscovery {
+    type Transport = BluetoothTransport;
+
+    fn filter(&self) -> String {
+        self.filter.clone()
+    }
+
+    async fn discover(&self) -> anyhow::Result<Option<&BluetoothTransport>> {
+        if self.transport.get().is_none() {
+            if let Some(transport) =
+                BluetoothTransport::discover(&self.adapter, &self.filter).await?
+            {
+                let _ = self.transport.set(transport);
+            }
+        }
+        Ok(self.transport.get())
+    }
+}
+
+impl Transport for BluetoothTransport {
+    async fn name(&self) -> anyhow::Result<String> {
+        let name = self
+            .peripheral
+            .properties()
+            .await?
+            .and_then(|properties| properties.local_name)
+            .unwrap_or_default();
+        Ok(name)
+    }
+
+    fn address(&self) -> String {
+        self.peripheral.address().to_string()
+    }
+
+    async fn subscribe(&self) -> anyhow::Result<BoxStream<'static, Vec<u8>>> {
+        let notify_characteristic = self.find_characteristic(CharPropFlags::NOTIFY).await?;
+
+        self.peripheral
+            .subscribe(&notify_characteristic)
+            .await
+            .context("Could not subscribe to characteristic")?;
+
+        // The notification stream does not end by itself when the device disconnects
+        let peripheral = self.peripheral.clone();
+        let disconnected = async move {
+            let mut interval = tokio::time::interval(CONNECTION_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                if !peripheral.is_connected().await.unwrap_or(false) {
+                    break;
+                }
+            }
+        };
+
+        let notification_stream = self.peripheral.notifications().await?;
+        Ok(notification_stream
+            .skip(1)
+            .map(|notification| notification.value)
+            .take_until(disconnected)
+            .boxed())
+    }
+
+    async fn write(&self, data: &[u8]) -> anyhow::Result<()> {
+        let write_characteristic = self.find_characteristic(CharPropFlags::WRITE).await?;
+
+        self.peripheral
+            .write(&write_characteristic, data, WriteType::WithoutResponse)
+            .await?;
+        Ok(())
+    }
+
+    async fn reconnect(&self) -> anyhow::Result<()> {
+        if !self.peripheral.is_connected().await? {
+            self.peripheral
+                .connect()
+                .await
+                .context("Could not reconnect")?;
+        }
+        Ok(())
+    }
+}
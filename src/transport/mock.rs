@@ -0,0 +1,97 @@
+use super::{NotReconnectableError, Transport, MOCK_ADDRESS};
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    stream::BoxStream,
+    StreamExt,
+};
+use std::sync::Mutex;
+
+type Responder = Box<dyn Fn(&[u8]) -> Vec<Vec<u8>>>;
+
+/// In-memory transport that plays back scripted notifications
+pub struct MockTransport {
+    sender: Mutex<Option<UnboundedSender<Vec<u8>>>>,
+    receiver: Mutex<Option<UnboundedReceiver<Vec<u8>>>>,
+    writes: Mutex<Vec<Vec<u8>>>,
+    responder: Option<Responder>,
+    reconnectable: bool,
+}
+
+impl MockTransport {
+    pub fn new(notifications: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        let (sender, receiver) = mpsc::unbounded();
+        for notification in notifications {
+            sender.unbounded_send(notification).unwrap();
+        }
+
+        Self {
+            sender: Mutex::new(Some(sender)),
+            receiver: Mutex::new(Some(receiver)),
+            writes: Mutex::new(Vec::new()),
+            responder: None,
+            reconnectable: true,
+        }
+    }
+
+    /// Sets a function that produces the notifications sent in response to a write
+    pub fn with_responder(mut self, responder: impl Fn(&[u8]) -> Vec<Vec<u8>> + 'static) -> Self {
+        self.responder = Some(Box::new(responder));
+        self
+    }
+
+    /// Makes every reconnect fail like at the end of a replayed capture
+    pub fn not_reconnectable(mut self) -> Self {
+        self.reconnectable = false;
+        self
+    }
+
+    /// Ends the notification stream after the already queued notifications
+    pub fn close(&self) {
+        self.sender.lock().unwrap().take();
+    }
+
+    pub fn writes(&self) -> Vec<Vec<u8>> {
+        self.writes.lock().unwrap().clone()
+    }
+}
+
+impl Transport for MockTransport {
+    async fn name(&self) -> anyhow::Result<String> {
+        Ok("Mock".to_owned())
+    }
+
+    fn address(&self) -> String {
+        MOCK_ADDRESS.to_owned()
+    }
+
+    async fn subscribe(&self) -> anyhow::Result<BoxStream<'static, Vec<u8>>> {
+        let receiver = self.receiver.lock().unwrap().take();
+        match receiver {
+            Some(receiver) => Ok(receiver.boxed()),
+            None => anyhow::bail!("Already subscribed"),
+        }
+    }
+
+    async fn write(&self, data: &[u8]) -> anyhow::Result<()> {
+        self.writes.lock().unwrap().push(data.to_vec());
+
+        if let Some(responder) = &self.responder {
+            if let Some(sender) = self.sender.lock().unwrap().as_ref() {
+                for notification in responder(data) {
+                    sender.unbounded_send(notification)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn reconnect(&self) -> anyhow::Result<()> {
+        if !self.reconnectable {
+            return Err(NotReconnectableError {
+                reason: "Mock is not reconnectable",
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use super::{NotReconnectableError, Transport, REPLAY_ADDRESS};
+use crate::hex;
+use anyhow::Context;
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use std::{fs, path::Path, time::Duration};
+
+/// Plays back notifications saved by the `record` command.
+///
+/// Each line of a capture holds the unix timestamp of the notification in seconds
+/// and its value as hex, separated by a space.
+pub struct ReplayTransport {
+    name: String,
+    entries: Vec<CaptureEntry>,
+    original_speed: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CaptureEntry {
+    pub timestamp: f64,
+    pub data: Vec<u8>,
+}
+
+impl ReplayTransport {
+    /// Loads a capture file, with `original_speed` the notifications are delayed the same way they were received
+    pub fn open(path: &Path, original_speed: bool) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Could not read capture '{}'", path.display()))?;
+        let entries = parse_capture(&contents)
+            .with_context(|| format!("Could not parse capture '{}'", path.display()))?;
+
+        Ok(Self {
+            name: path.display().to_string(),
+            entries,
+            original_speed,
+        })
+    }
+}
+
+impl Transport for ReplayTransport {
+    async fn name(&self) -> anyhow::Result<String> {
+        Ok(self.name.clone())
+    }
+
+    fn address(&self) -> String {
+        REPLAY_ADDRESS.to_owned()
+    }
+
+    async fn subscribe(&self) -> anyhow::Result<BoxStream<'static, Vec<u8>>> {
+        let mut previous_timestamp = self.entries.first().map(|entry| entry.timestamp);
+        let original_speed = self.original_speed;
+
+        let notifications = self.entries.clone().into_iter().map(move |entry| {
+            let delay = previous_timestamp
+                .map(|previous| (entry.timestamp - previous).max(0.0))
+                .unwrap_or_default();
+            previous_timestamp = Some(entry.timestamp);
+            // Timestamps are finite, but can still be too far apart for a Duration
+            let delay = Duration::try_from_secs_f64(delay).unwrap_or(Duration::MAX);
+            (delay, entry.data)
+        });
+
+        Ok(stream::iter(notifications)
+            .then(move |(delay, data)| async move {
+                if original_speed {
+                    tokio::time::sleep(delay).await;
+                }
+                data
+            })
+            .boxed())
+    }
+
+    async fn write(&self, _data: &[u8]) -> anyhow::Result<()> {
+        anyhow::bail!("Cannot send requests to a replayed capture")
+    }
+
+    async fn reconnect(&self) -> anyhow::Result<()> {
+        Err(NotReconnectableError {
+            reason: "The replayed capture has ended",
+        }
+        .into())
+    }
+}
+
+pub fn format_capture_entry(entry: &CaptureEntry) -> String {
+    format!("{:.3} {}", entry.timestamp, hex::encode(&entry.data))
+}
+
+/// Parses a capture, skipping empty lines and comments starting with `#`
+pub fn parse_capture(contents: &str) -> anyhow::Result<Vec<CaptureEntry>> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| {
+            let (timestamp, data) = line
+                .split_once(' ')
+                .with_context(|| format!("Missing timestamp on line {line_number}"))?;
+
+            let timestamp = timestamp
+                .parse::<f64>()
+                .ok()
+                .filter(|timestamp| timestamp.is_finite())
+                .with_context(|| format!("Invalid timestamp on line {line_number}"))?;
+
+            Ok(CaptureEntry {
+                timestamp,
+                data: hex::decode(data)
+                    .with_context(|| format!("Invalid data on line {line_number}"))?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_capture_entry, parse_capture, CaptureEntry, ReplayTransport};
+    use crate::transport::{NotReconnectableError, Transport};
+    use futures::StreamExt;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    fn entries() -> Vec<CaptureEntry> {
+        vec![
+            CaptureEntry {
+                timestamp: 1718000000.5,
+                data: vec![0x5a, 0xa5, 0xc0, 0xa1],
+            },
+            CaptureEntry {
+                timestamp: 1718000002.5,
+                data: vec![0x00, 0x01],
+            },
+        ]
+    }
+
+    #[test]
+    fn format_and_parse() {
+        let capture = entries()
+            .iter()
+            .map(format_capture_entry)
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!("1718000000.500 5aa5c0a1\n1718000002.500 0001", capture);
+
+        let capture = format!("# Recorded by proam-cli\n\n{capture}\n");
+        assert_eq!(entries(), parse_capture(&capture).unwrap());
+    }
+
+    #[test]
+    fn invalid_line() {
+        let err = parse_capture("1718000000.5 5aa5\nfoo 00").unwrap_err();
+        assert_eq!("Invalid timestamp on line 2", err.to_string());
+
+        let err = parse_capture("inf 5aa5").unwrap_err();
+        assert_eq!("Invalid timestamp on line 1", err.to_string());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn original_speed() {
+        let transport = ReplayTransport {
+            name: "test".to_owned(),
+            entries: entries(),
+            original_speed: true,
+        };
+
+        let start = Instant::now();
+        let notifications: Vec<_> = transport.subscribe().await.unwrap().collect().await;
+        assert_eq!(
+            vec![vec![0x5a, 0xa5, 0xc0, 0xa1], vec![0x00, 0x01]],
+            notifications
+        );
+        assert_eq!(Duration::from_secs(2), start.elapsed());
+    }
+
+    #[tokio::test]
+    async fn not_reconnectable() {
+        let transport = ReplayTransport {
+            name: "test".to_owned(),
+            entries: entries(),
+            original_speed: false,
+        };
+        let err = transport.reconnect().await.unwrap_err();
+        assert!(err.is::<NotReconnectableError>());
+    }
+}
@@ -0,0 +1,332 @@
+use super::{Transport, SIMULATOR_ADDRESS};
+use crate::protocol::{
+    device_info::{
+        CapacityInfo, DcPower, FlashlightMode, Power, Status, TotalPower, VersionInfo, CELL_COUNT,
+    },
+    frame::FrameDecoder,
+    notification::StatsUpdate,
+    request::Request,
+};
+use futures::{
+    channel::mpsc::{self, UnboundedSender},
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use std::{
+    f64::consts::PI,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+/// Simulated seconds per update, so a whole day passes in 24 minutes
+const TIME_SCALE: f64 = 60.0;
+const CAPACITY_WH: f64 = 680.0;
+const CAPACITY_MAH: f64 = 30400.0;
+const SOLAR_PEAK_POWER: f64 = 200.0;
+/// Each step of the AC load lasts for `LOAD_STEP_UPDATES` updates
+const AC_LOAD_PATTERN: [f64; 5] = [45.0, 120.0, 60.0, 0.0, 300.0];
+const LOAD_STEP_UPDATES: u64 = 30;
+const CELL_OFFSETS: [f64; CELL_COUNT] = [0.0, 12.0, -8.0, 5.0, -3.0, 9.0, -6.0];
+/// The device pads all notifications to the same length
+const NOTIFICATION_LEN: usize = 128;
+
+/// Software model of a power station that can be used instead of a real device.
+///
+/// Sends the same notifications as the device with a day/night solar input cycle
+/// and a changing load, and applies the requests written to it.
+#[derive(Clone, Default)]
+pub struct SimulatedPowerRoam {
+    state: Arc<Mutex<State>>,
+    subscribers: Arc<Mutex<Vec<UnboundedSender<Vec<u8>>>>>,
+}
+
+impl SimulatedPowerRoam {
+    fn notify(&self, updates: &[StatsUpdate]) {
+        let notification = notification(updates);
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|sender| sender.unbounded_send(notification.clone()).is_ok());
+    }
+}
+
+impl Transport for SimulatedPowerRoam {
+    async fn name(&self) -> anyhow::Result<String> {
+        Ok("Simulated PowerRoam".to_owned())
+    }
+
+    fn address(&self) -> String {
+        SIMULATOR_ADDRESS.to_owned()
+    }
+
+    async fn subscribe(&self) -> anyhow::Result<BoxStream<'static, Vec<u8>>> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.subscribers.lock().unwrap().push(sender);
+
+        let state = self.state.clone();
+        let updates = stream::unfold(
+            tokio::time::interval(UPDATE_INTERVAL),
+            move |mut interval| {
+                let state = state.clone();
+                async move {
+                    interval.tick().await;
+                    let notifications = state.lock().unwrap().step();
+                    Some((stream::iter(notifications), interval))
+                }
+            },
+        )
+        .flatten();
+
+        Ok(stream::select(updates, receiver).boxed())
+    }
+
+    async fn write(&self, data: &[u8]) -> anyhow::Result<()> {
+        for frame in FrameDecoder::for_requests().decode(data) {
+            let Some(request) = frame.ok().as_ref().and_then(Request::from_frame) else {
+                continue;
+            };
+
+            let response = {
+                let mut state = self.state.lock().unwrap();
+                match request {
+                    Request::Version => StatsUpdate::Version(VersionInfo {
+                        firmware: env!("CARGO_PKG_VERSION").to_owned(),
+                        hardware: "simulated".to_owned(),
+                        serial_number: None,
+                        raw: String::new(),
+                    }),
+                    Request::Flashlight(mode) => {
+                        state.flashlight = mode;
+                        StatsUpdate::FlashlightStatus(mode)
+                    }
+                }
+            };
+            self.notify(&[response]);
+        }
+        Ok(())
+    }
+
+    async fn reconnect(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+struct State {
+    updates: u64,
+    energy: f64,
+    status: Status,
+    flashlight: FlashlightMode,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            updates: 0,
+            energy: CAPACITY_WH * 0.8,
+            status: Status {
+                usb_switch: true,
+                dc_switch: true,
+                ac_switch: true,
+                battery_health: true,
+                key_voice: true,
+                ..Default::default()
+            },
+            flashlight: FlashlightMode::Off,
+        }
+    }
+}
+
+impl State {
+    /// Advances the simulation by one update and returns the notifications describing the new state
+    fn step(&mut self) -> Vec<Vec<u8>> {
+        self.updates += 1;
+
+        let hour = (self.updates as f64 * TIME_SCALE / 3600.0) % 24.0;
+        // The sun is up between 6:00 and 18:00
+        let mut input = ((hour - 6.0) / 12.0 * PI).sin().max(0.0) * SOLAR_PEAK_POWER;
+
+        let ac_power = if self.status.ac_switch {
+            let step = (self.updates / LOAD_STEP_UPDATES) as usize % AC_LOAD_PATTERN.len();
+            AC_LOAD_PATTERN[step]
+        } else {
+            0.0
+        };
+
+        let mut dc_power = DcPower::default();
+        if self.status.usb_switch {
+            dc_power.type_c_one_power = 18;
+            // A laptop that charges for half of the time
+            dc_power.type_c_two_power = if self.updates % 120 < 60 { 45 } else { 0 };
+            dc_power.usb_one_power = 5;
+        }
+        dc_power.total = dc_power.type_c_one_power
+            + dc_power.type_c_two_power
+            + dc_power.usb_one_power
+            + dc_power.usb_two_power;
+        if self.status.dc_switch {
+            dc_power.total += 12;
+        }
+
+        let flashlight_power = match self.flashlight {
+            FlashlightMode::Off => 0.0,
+            FlashlightMode::Low => 1.0,
+            FlashlightMode::High => 3.0,
+            FlashlightMode::Strobe | FlashlightMode::Sos => 2.0,
+        };
+
+        // Inverter losses
+        let output = ac_power * 1.1 + f64::from(dc_power.total) + flashlight_power;
+        if self.energy >= CAPACITY_WH {
+            input = input.min(output);
+        }
+
+        let net_power = input - output;
+        self.energy = (self.energy + net_power * TIME_SCALE / 3600.0).clamp(0.0, CAPACITY_WH);
+
+        if self.energy == 0.0 {
+            self.status.ac_switch = false;
+            self.status.dc_switch = false;
+            self.status.usb_switch = false;
+        }
+
+        let charge = self.energy / CAPACITY_WH;
+        self.status.low_battery_warning = charge < 0.1;
+
+        let charge_time = if net_power > 0.0 {
+            minutes((CAPACITY_WH - self.energy) / net_power)
+        } else {
+            u16::MAX
+        };
+        let discharge_time = if net_power < 0.0 {
+            minutes(self.energy / -net_power)
+        } else {
+            u16::MAX
+        };
+
+        let cell_voltages = CELL_OFFSETS.map(|offset| (3100.0 + charge * 300.0 + offset) as u16);
+        let pack_voltage = cell_voltages
+            .iter()
+            .map(|&cell| u32::from(cell))
+            .sum::<u32>()
+            / 100;
+
+        let capacity = CapacityInfo {
+            charge_time,
+            discharge_time,
+            battery_capacity_power: (charge * 100.0).round() as u8,
+            pack_voltage: pack_voltage as u16,
+            cell_voltages,
+            temperature: (2500.0 + output * 5.0) as u16,
+            remaining_capacity: (charge * CAPACITY_MAH) as u16,
+        };
+
+        vec![
+            notification(&[
+                StatsUpdate::TotalPower(TotalPower {
+                    input: input as u16,
+                    output: output as u16,
+                }),
+                StatsUpdate::AcPower(ac_power as u16),
+            ]),
+            notification(&[
+                StatsUpdate::Status(self.status),
+                StatsUpdate::FlashlightStatus(self.flashlight),
+                StatsUpdate::Key17([0; 6]),
+            ]),
+            notification(&[
+                StatsUpdate::DcPower(dc_power),
+                StatsUpdate::ElectricQuantityPower(0),
+            ]),
+            notification(&[
+                StatsUpdate::Power(Power {
+                    batteries_one_power: output as u16,
+                    batteries_two_power: input as u16,
+                    inverter_one_power: (ac_power * 1.1) as u16,
+                    inverter_two_power: ac_power as u16,
+                }),
+                StatsUpdate::Capacity(capacity),
+            ]),
+        ]
+    }
+}
+
+fn minutes(hours: f64) -> u16 {
+    (hours * 60.0).min(f64::from(u16::MAX - 1)) as u16
+}
+
+fn notification(updates: &[StatsUpdate]) -> Vec<u8> {
+    let mut data: Vec<u8> = updates
+        .iter()
+        .flat_map(|update| update.to_frame().encode())
+        .collect();
+    data.resize(data.len().max(NOTIFICATION_LEN), 0);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SimulatedPowerRoam, State};
+    use crate::{
+        protocol::{
+            device_info::FlashlightMode,
+            frame::FrameDecoder,
+            notification::{process_notification, StatsUpdate},
+            request::Request,
+        },
+        transport::Transport,
+    };
+    use futures::StreamExt;
+
+    fn decode(notifications: &[Vec<u8>]) -> Vec<StatsUpdate> {
+        let mut decoder = FrameDecoder::default();
+        notifications
+            .iter()
+            .flat_map(|notification| process_notification(&mut decoder, notification))
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn discharges_at_night() {
+        let mut state = State::default();
+        let initial_energy = state.energy;
+
+        let updates = decode(&state.step());
+        assert_eq!(9, updates.len());
+        assert!(state.energy < initial_energy);
+
+        let capacity = updates
+            .iter()
+            .find_map(|update| match update {
+                StatsUpdate::Capacity(capacity) => Some(*capacity),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(u16::MAX, capacity.charge_time);
+        assert_eq!(80, capacity.battery_capacity_power);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn applies_requests() {
+        let simulator = SimulatedPowerRoam::default();
+        let mut notifications = simulator.subscribe().await.unwrap();
+
+        simulator
+            .write(&Request::Flashlight(FlashlightMode::Strobe).encode())
+            .await
+            .unwrap();
+
+        let mut decoder = FrameDecoder::default();
+        while let Some(notification) = notifications.next().await {
+            let updates = process_notification(&mut decoder, &notification);
+            if updates.contains(&Ok(StatsUpdate::FlashlightStatus(FlashlightMode::Strobe))) {
+                break;
+            }
+        }
+        assert_eq!(
+            FlashlightMode::Strobe,
+            simulator.state.lock().unwrap().flashlight
+        );
+    }
+}
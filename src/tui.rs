@@ -0,0 +1,445 @@
+use crate::{
+    commands::write_confirmed_with,
+    display::{
+        format_minutes, format_net_power, format_temperature, format_voltage, settings, switch,
+        watts,
+    },
+    protocol::{
+        device_info::{DeviceInfo, FlashlightMode},
+        error::ParseError,
+        notification::StatsUpdate,
+        request::Request,
+    },
+    transport::Transport,
+};
+use crossterm::event::{Event, EventStream, KeyCode, KeyEventKind};
+use futures::{
+    channel::mpsc::{self, UnboundedSender},
+    future::{self, LocalBoxFuture},
+    FutureExt, Stream, StreamExt,
+};
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{Block, Gauge, Paragraph, Sparkline},
+    DefaultTerminal, Frame,
+};
+use std::collections::VecDeque;
+use strum::IntoEnumIterator;
+
+/// Number of samples kept for the history graphs
+const HISTORY_LEN: usize = 600;
+
+/// Shows the device state on a full-screen dashboard until the user quits
+pub async fn run(
+    transport: &impl Transport,
+    name: &str,
+    mut stream: impl Stream<Item = Result<StatsUpdate, ParseError>> + Unpin,
+    retries: u32,
+) -> anyhow::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, transport, name, &mut stream, retries).await;
+    ratatui::restore();
+    result
+}
+
+async fn event_loop(
+    terminal: &mut DefaultTerminal,
+    transport: &impl Transport,
+    name: &str,
+    stream: &mut (impl Stream<Item = Result<StatsUpdate, ParseError>> + Unpin),
+    retries: u32,
+) -> anyhow::Result<()> {
+    let mut dashboard = Dashboard::new(name);
+    let mut events = EventStream::new();
+    let mut pending: Option<PendingRequest> = None;
+
+    loop {
+        terminal.draw(|frame| dashboard.draw(frame))?;
+
+        tokio::select! {
+            update = stream.next() => match update {
+                Some(result) => {
+                    if let (Some(pending), Ok(update)) = (&pending, &result) {
+                        // Fails only if the request has finished, then the update isn't needed anymore
+                        let _ = pending.updates.unbounded_send(update.clone());
+                    }
+                    dashboard.update(result);
+                }
+                None => anyhow::bail!("Notification stream ended"),
+            },
+            result = confirmation(&mut pending) => {
+                if let Some(PendingRequest { request, .. }) = pending.take() {
+                    dashboard.message = match result {
+                        Ok(()) => format!("Set {}", describe(request)),
+                        Err(err) => format!("Could not set {}: {err}", describe(request)),
+                    };
+                }
+            }
+            event = events.next() => {
+                let Some(event) = event else {
+                    return Ok(());
+                };
+                let Event::Key(key) = event? else {
+                    continue;
+                };
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+
+                if matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                    return Ok(());
+                }
+                let Some(request) = dashboard.request_for_key(key.code) else {
+                    continue;
+                };
+                dashboard.message = match &pending {
+                    Some(pending) => format!("Still requesting {}...", describe(pending.request)),
+                    None => {
+                        pending = Some(PendingRequest::new(transport, request, retries));
+                        format!("Requesting {}...", describe(request))
+                    }
+                };
+            }
+        }
+    }
+}
+
+/// Request that was sent to the device and is waiting for the confirmation.
+///
+/// The dashboard keeps running meanwhile and passes the updates it receives on to `updates`.
+struct PendingRequest<'a> {
+    request: Request,
+    updates: UnboundedSender<StatsUpdate>,
+    confirmed: LocalBoxFuture<'a, anyhow::Result<()>>,
+}
+
+impl<'a> PendingRequest<'a> {
+    fn new(transport: &'a impl Transport, request: Request, retries: u32) -> Self {
+        let (updates, mut receiver) = mpsc::unbounded();
+        let confirmed = async move {
+            // Retries can't be printed while the dashboard is shown
+            write_confirmed_with(transport, &mut receiver, request, retries, |_| ()).await?;
+            Ok(())
+        };
+
+        Self {
+            request,
+            updates,
+            confirmed: confirmed.boxed_local(),
+        }
+    }
+}
+
+/// Waits until the pending request is confirmed or has failed, never completes without one
+async fn confirmation(pending: &mut Option<PendingRequest<'_>>) -> anyhow::Result<()> {
+    match pending {
+        Some(pending) => (&mut pending.confirmed).await,
+        None => future::pending().await,
+    }
+}
+
+struct Dashboard {
+    name: String,
+    info: DeviceInfo,
+    received: bool,
+    input_history: VecDeque<u64>,
+    output_history: VecDeque<u64>,
+    charge_history: VecDeque<u64>,
+    parse_errors: u64,
+    message: String,
+}
+
+impl Dashboard {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            info: DeviceInfo::default(),
+            received: false,
+            input_history: VecDeque::new(),
+            output_history: VecDeque::new(),
+            charge_history: VecDeque::new(),
+            parse_errors: 0,
+            message: String::new(),
+        }
+    }
+
+    fn update(&mut self, result: Result<StatsUpdate, ParseError>) {
+        let Ok(update) = result else {
+            self.parse_errors += 1;
+            return;
+        };
+
+        match &update {
+            StatsUpdate::TotalPower(total) => {
+                push_sample(&mut self.input_history, total.input.into());
+                push_sample(&mut self.output_history, total.output.into());
+            }
+            StatsUpdate::Capacity(capacity) => {
+                push_sample(
+                    &mut self.charge_history,
+                    capacity.battery_capacity_power.into(),
+                );
+            }
+            _ => (),
+        }
+
+        self.received = true;
+        self.info.apply_update(update);
+    }
+
+    /// Request that should be sent when the key is pressed
+    fn request_for_key(&self, key: KeyCode) -> Option<Request> {
+        match key {
+            KeyCode::Char('f') => FlashlightMode::iter()
+                .cycle()
+                .skip_while(|mode| *mode != self.info.flashlight)
+                .nth(1)
+                .map(Request::Flashlight),
+            _ => None,
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [header, panels, graphs, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(10),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(
+            Line::from(format!(" {}  |  q: quit  f: cycle flashlight", self.name)).bold(),
+            header,
+        );
+
+        let mut status_line = format!(" {}", self.message);
+        if self.parse_errors > 0 {
+            status_line.push_str(&format!("  ({} parse errors)", self.parse_errors));
+        }
+        frame.render_widget(Line::from(status_line), footer);
+
+        if !self.received {
+            frame.render_widget(Paragraph::new(" Waiting for data..."), panels);
+            return;
+        }
+
+        let [battery, power, outputs, settings] =
+            Layout::horizontal([Constraint::Ratio(1, 4); 4]).areas(panels);
+        self.draw_battery(frame, battery);
+        self.draw_power(frame, power);
+        self.draw_outputs(frame, outputs);
+        self.draw_settings(frame, settings);
+
+        let [input, output, charge] = Layout::vertical([Constraint::Ratio(1, 3); 3]).areas(graphs);
+        draw_history(frame, input, "Input (W)", &self.input_history, None);
+        draw_history(frame, output, "Output (W)", &self.output_history, None);
+        draw_history(
+            frame,
+            charge,
+            "Battery (%)",
+            &self.charge_history,
+            Some(100),
+        );
+    }
+
+    fn draw_battery(&self, frame: &mut Frame, area: Rect) {
+        let capacity = &self.info.capacity;
+        let block = Block::bordered().title("Battery");
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        let [gauge, details] =
+            Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(inner);
+        frame.render_widget(
+            Gauge::default()
+                .gauge_style(Style::new().green())
+                .percent(capacity.battery_capacity_power.min(100).into()),
+            gauge,
+        );
+
+        let lines = vec![
+            Line::from(format!(
+                "Until full: {}",
+                format_minutes(capacity.charge_time)
+            )),
+            Line::from(format!(
+                "Until empty: {}",
+                format_minutes(capacity.discharge_time)
+            )),
+            Line::from(format!("Voltage: {}", format_voltage(capacity))),
+            Line::from(format!("Temperature: {}", format_temperature(capacity))),
+            Line::from(format!("Remaining: {} mAh", capacity.remaining_capacity)),
+        ];
+        frame.render_widget(Paragraph::new(lines), details);
+    }
+
+    fn draw_power(&self, frame: &mut Frame, area: Rect) {
+        let total = &self.info.total_power;
+        let lines = vec![
+            Line::from(format!("Input: {}", watts(total.input))),
+            Line::from(format!("Output: {}", watts(total.output))),
+            Line::from(format!("Net: {}", format_net_power(total))),
+            Line::from(format!("AC: {}", watts(self.info.ac_power))),
+            Line::from(format!("DC: {}", watts(self.info.dc_power.total))),
+        ];
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("Power")),
+            area,
+        );
+    }
+
+    fn draw_outputs(&self, frame: &mut Frame, area: Rect) {
+        let status = &self.info.status;
+        let dc = &self.info.dc_power;
+        let lines = vec![
+            Line::from(format!("AC: {}", switch(status.ac_switch))),
+            Line::from(format!("DC: {}", switch(status.dc_switch))),
+            Line::from(format!("USB: {}", switch(status.usb_switch))),
+            Line::from(format!("USB-C1: {}", watts(dc.type_c_one_power))),
+            Line::from(format!("USB-C2: {}", watts(dc.type_c_two_power))),
+            Line::from(format!("USB-A1: {}", watts(dc.usb_one_power))),
+            Line::from(format!("USB-A2: {}", watts(dc.usb_two_power))),
+        ];
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("Outputs")),
+            area,
+        );
+    }
+
+    fn draw_settings(&self, frame: &mut Frame, area: Rect) {
+        let status = &self.info.status;
+        let mut lines = vec![Line::from(format!("Flashlight: {}", self.info.flashlight))];
+        lines.extend(settings().map(|setting| {
+            Line::from(format!(
+                "{setting}: {}",
+                setting.format_value(status.setting(setting))
+            ))
+        }));
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("Settings")),
+            area,
+        );
+    }
+}
+
+fn draw_history(
+    frame: &mut Frame,
+    area: Rect,
+    title: &str,
+    samples: &VecDeque<u64>,
+    max: Option<u64>,
+) {
+    let block = Block::bordered().title(format!(
+        "{title} {}",
+        samples.back().copied().unwrap_or_default()
+    ));
+    // Only the most recent samples fit into the graph
+    let width = usize::from(block.inner(area).width);
+    let shown = samples
+        .range(samples.len().saturating_sub(width)..)
+        .copied();
+
+    let mut sparkline = Sparkline::default().block(block).data(shown);
+    if let Some(max) = max {
+        sparkline = sparkline.max(max);
+    }
+    frame.render_widget(sparkline, area);
+}
+
+fn push_sample(history: &mut VecDeque<u64>, value: u64) {
+    if history.len() == HISTORY_LEN {
+        history.pop_front();
+    }
+    history.push_back(value);
+}
+
+fn describe(request: Request) -> String {
+    match request {
+        Request::Flashlight(mode) => format!("flashlight {mode}"),
+        other => format!("{other:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{confirmation, Dashboard, PendingRequest};
+    use crate::{
+        protocol::{
+            device_info::{CapacityInfo, FlashlightMode},
+            notification::StatsUpdate,
+            request::Request,
+        },
+        transport::mock::MockTransport,
+    };
+    use crossterm::event::KeyCode;
+    use ratatui::{backend::TestBackend, Terminal};
+    use std::time::Duration;
+
+    #[test]
+    fn key_requests() {
+        let mut dashboard = Dashboard::new("Test");
+        assert_eq!(
+            Some(Request::Flashlight(FlashlightMode::Low)),
+            dashboard.request_for_key(KeyCode::Char('f'))
+        );
+
+        dashboard.update(Ok(StatsUpdate::FlashlightStatus(FlashlightMode::Sos)));
+
+        assert_eq!(
+            Some(Request::Flashlight(FlashlightMode::Off)),
+            dashboard.request_for_key(KeyCode::Char('f'))
+        );
+        // Outputs can't be toggled until the request encoding is known
+        assert_eq!(None, dashboard.request_for_key(KeyCode::Char('a')));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn confirmed_request() {
+        assert!(
+            tokio::time::timeout(Duration::from_secs(60), confirmation(&mut None))
+                .await
+                .is_err()
+        );
+
+        let transport = MockTransport::new([]);
+        let request = Request::Flashlight(FlashlightMode::High);
+        let mut pending = Some(PendingRequest::new(&transport, request, 2));
+
+        let updates = &pending.as_ref().unwrap().updates;
+        for mode in [FlashlightMode::Low, FlashlightMode::High] {
+            updates
+                .unbounded_send(StatsUpdate::FlashlightStatus(mode))
+                .unwrap();
+        }
+        confirmation(&mut pending).await.unwrap();
+
+        assert_eq!(vec![request.encode()], transport.writes());
+    }
+
+    #[test]
+    fn draw() {
+        let mut dashboard = Dashboard::new("Test");
+        dashboard.update(Ok(StatsUpdate::Capacity(CapacityInfo {
+            battery_capacity_power: 42,
+            discharge_time: 90,
+            ..Default::default()
+        })));
+
+        let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+        terminal.draw(|frame| dashboard.draw(frame)).unwrap();
+
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("42%"));
+        assert!(screen.contains("Until empty: 1h 30m"));
+        assert!(screen.contains("Battery (%) 42"));
+    }
+}